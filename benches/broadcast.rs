@@ -0,0 +1,131 @@
+// Compares fanning out strategy events by looking up and sending to every peer
+// (the previous broadcast) with publishing once to a per-strategy hub that each
+// peer forwards from.
+//
+//     cargo bench --bench broadcast
+
+use moka::sync::Cache;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinSet,
+};
+
+const EVENTS: usize = 2000;
+const PEER_COUNTS: [usize; 4] = [2, 10, 100, 1000];
+const PEER_BUFFER: usize = 32;
+const HUB_CAPACITY: usize = 256;
+const PAYLOAD_SIZE: usize = 64;
+
+#[derive(Clone)]
+struct Event(Vec<u8>);
+
+async fn drain(rx: &mut mpsc::Receiver<Event>) {
+    while let Some(event) = rx.recv().await {
+        std::hint::black_box(event.0);
+    }
+}
+
+async fn per_peer_broadcast(peers: usize) -> Duration {
+    let peer_context: Cache<String, Arc<mpsc::Sender<Event>>> = Cache::new(65536);
+    let tokens: Vec<String> = (0..peers).map(|peer| peer.to_string()).collect();
+
+    for token in &tokens {
+        let (tx, mut rx) = mpsc::channel::<Event>(PEER_BUFFER);
+        peer_context.insert(token.clone(), Arc::new(tx));
+        tokio::spawn(async move { drain(&mut rx).await });
+    }
+
+    let origin = &tokens[0];
+    let start = Instant::now();
+
+    for _ in 0..EVENTS {
+        let event = Event(vec![0; PAYLOAD_SIZE]);
+        let mut sends = JoinSet::new();
+
+        for token in tokens.iter().filter(|&token| token != origin) {
+            let tx = match peer_context.get(token) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let event = event.clone();
+            sends.spawn(async move { tx.send(event).await.unwrap() });
+        }
+
+        while sends.join_next().await.is_some() {}
+    }
+
+    let elapsed = start.elapsed();
+    peer_context.invalidate_all();
+
+    elapsed
+}
+
+async fn hub_broadcast(peers: usize) -> Duration {
+    let (hub, _) = broadcast::channel::<Arc<(String, Event)>>(HUB_CAPACITY);
+    let mut receivers = Vec::new();
+
+    for peer in 0..peers {
+        let token = peer.to_string();
+        let (tx, mut rx) = mpsc::channel::<Event>(PEER_BUFFER);
+        let mut subscription = hub.subscribe();
+
+        tokio::spawn(async move {
+            while let Ok(published) = subscription.recv().await {
+                if published.0 == token {
+                    continue;
+                }
+                if tx.send(published.1.clone()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receivers.push(tokio::spawn(async move { drain(&mut rx).await }));
+    }
+
+    let origin = "0".to_string();
+    let start = Instant::now();
+
+    for _ in 0..EVENTS {
+        // Stay below the hub capacity so that no peer lags and drops events.
+        while hub.len() >= HUB_CAPACITY - PEER_BUFFER {
+            tokio::task::yield_now().await;
+        }
+        hub.send(Arc::new((origin.clone(), Event(vec![0; PAYLOAD_SIZE]))))
+            .ok();
+    }
+
+    drop(hub);
+    for receiver in receivers {
+        receiver.await.unwrap();
+    }
+
+    start.elapsed()
+}
+
+fn per_event(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() * 1e6 / EVENTS as f64
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "{:>6} {:>14} {:>14}",
+        "peers", "per-peer µs/ev", "hub µs/ev"
+    );
+
+    for peers in PEER_COUNTS {
+        let per_peer = per_peer_broadcast(peers).await;
+        let hub = hub_broadcast(peers).await;
+
+        println!(
+            "{:>6} {:>14.1} {:>14.1}",
+            peers,
+            per_event(per_peer),
+            per_event(hub)
+        );
+    }
+}
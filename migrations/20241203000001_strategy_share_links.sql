@@ -0,0 +1,12 @@
+CREATE TYPE public.share_scope AS ENUM ('view', 'edit', 'notes');
+
+CREATE TABLE public.strategy_share_links (
+    id uuid PRIMARY KEY,
+    strategy uuid NOT NULL REFERENCES public.strategies (id) ON DELETE CASCADE,
+    scope public.share_scope NOT NULL,
+    expires_at timestamptz NOT NULL,
+    revoked boolean NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX strategy_share_links_strategy_idx ON public.strategy_share_links (strategy);
@@ -0,0 +1,8 @@
+CREATE TYPE public.collaborator_role AS ENUM ('viewer', 'editor', 'owner');
+
+CREATE TABLE public.strategy_collaborators (
+    strategy uuid NOT NULL REFERENCES public.strategies (id) ON DELETE CASCADE,
+    "user" uuid NOT NULL,
+    role public.collaborator_role NOT NULL,
+    PRIMARY KEY (strategy, "user")
+);
@@ -1,5 +1,6 @@
 mod rpc;
 mod service;
+mod strategy;
 
 pub mod protos;
 pub mod types;
@@ -9,11 +9,11 @@ impl StratSyncService {
         &self,
         request: Request<ClearOtherSessionsRequest>,
     ) -> Result<Response<()>, Status> {
-        let payload = request.into_inner();
+        let session = utils::parse_session(&request).await?;
 
         utils::open_strategy_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
@@ -27,11 +27,11 @@ impl StratSyncService {
         }
 
         for peer in &strategy_context.peers {
-            if &payload.token == peer {
+            if &session.token == peer {
                 continue;
             }
 
-            self.peer_context.invalidate(peer);
+            self.kick(peer, Some("Session cleared by the author".to_string()));
         }
 
         Ok(Response::new(()))
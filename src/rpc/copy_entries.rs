@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_copy_entries(
+        &self,
+        request: Request<CopyEntriesRequest>,
+    ) -> Result<Response<CopyEntriesResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        let source_player_id = utils::parse_string_to_uuid(
+            &payload.source_player,
+            "source_player has an invalid format",
+        )?;
+        let target_player_id = utils::parse_string_to_uuid(
+            &payload.target_player,
+            "target_player has an invalid format",
+        )?;
+        let action_filter: HashSet<Uuid> = payload
+            .actions
+            .iter()
+            .map(|id| utils::parse_string_to_uuid(id, "action has an invalid format"))
+            .collect::<Result<_, _>>()?;
+
+        if source_player_id == target_player_id {
+            return Err(Status::invalid_argument(
+                "Cannot copy entries of a player onto itself",
+            ));
+        }
+
+        // Another strategy is read before taking the lease on this one, so that
+        // copies in opposite directions cannot wait on each other.
+        let remote_source = self.fetch_remote_source(&session, source_player_id).await?;
+
+        utils::open_strategy_elevated!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        strategy_context.check_pending_writes()?;
+
+        let target_player = strategy_context
+            .players
+            .iter()
+            .find(|player| player.id == target_player_id.to_string())
+            .ok_or_else(|| Status::failed_precondition("Target player not found"))?;
+
+        let target_job = target_player.job.as_ref().ok_or_else(|| {
+            Status::failed_precondition("Cannot copy entries onto a player with an empty job")
+        })?;
+
+        let (source_job, source_entries) = match remote_source {
+            Some(remote_source) => remote_source,
+            None => {
+                let source_player = strategy_context
+                    .players
+                    .iter()
+                    .find(|player| player.id == source_player_id.to_string())
+                    .ok_or_else(|| Status::failed_precondition("Source player not found"))?;
+
+                (
+                    source_player.job.clone(),
+                    player_entries(strategy_context, source_player_id),
+                )
+            }
+        };
+
+        if source_job.as_ref() != Some(target_job) {
+            return Err(Status::failed_precondition(
+                "Source and target players must have the same job",
+            ));
+        }
+
+        let action_lookup: HashMap<Uuid, ActionInfo> = self
+            .job_actions(target_job)
+            .iter()
+            .map(|action| (action.id, action.clone()))
+            .collect();
+
+        let mut grouped_copies: HashMap<Uuid, Vec<i32>> = HashMap::new();
+        for entry in source_entries {
+            if !action_filter.is_empty() && !action_filter.contains(&entry.action) {
+                continue;
+            }
+
+            if payload.from.is_some_and(|from| entry.use_at < from)
+                || payload.to.is_some_and(|to| entry.use_at > to)
+            {
+                continue;
+            }
+
+            grouped_copies
+                .entry(entry.action)
+                .or_default()
+                .push(entry.use_at);
+        }
+
+        let mut accepted_copies: Vec<(Uuid, EntryState)> = Vec::new();
+        let mut rejected_actions: Vec<String> = Vec::new();
+
+        for (action_id, use_ats) in grouped_copies {
+            let action = action_lookup
+                .get(&action_id)
+                .ok_or_else(|| Status::internal("Action not found"))?;
+
+            let use_ats_after = strategy_context
+                .entries
+                .column(target_player_id, action_id)
+                .map(|(_, use_at)| use_at)
+                .chain(use_ats.iter().copied());
+
+            if utils::max_simultaneous_uses(use_ats_after, action.cooldown) > action.charges {
+                rejected_actions.push(action_id.to_string());
+                continue;
+            }
+
+            accepted_copies.extend(use_ats.into_iter().map(|use_at| {
+                (
+                    Uuid::new_v4(),
+                    EntryState {
+                        player: target_player_id,
+                        action: action_id,
+                        use_at,
+                    },
+                )
+            }));
+        }
+
+        if !accepted_copies.is_empty() {
+            for &(id, entry) in &accepted_copies {
+                strategy_context.entries.insert(id, entry);
+            }
+            strategy_context.revision += 1;
+
+            self.persist_entries(
+                peer_context.strategy_id,
+                strategy_context,
+                accepted_copies
+                    .iter()
+                    .map(|&(id, entry)| (id, Some(entry)))
+                    .collect(),
+            )
+            .await;
+
+            self.broadcast(
+                &session.token,
+                strategy_context,
+                event_response::Event::MutateEntriesEvent(MutateEntriesEvent {
+                    upserts: accepted_copies
+                        .iter()
+                        .map(|(id, entry)| entry.to_entry(*id))
+                        .collect(),
+                    deletes: vec![],
+                }),
+            );
+        }
+
+        Ok(Response::new(CopyEntriesResponse {
+            entries: accepted_copies
+                .iter()
+                .map(|(id, entry)| entry.to_entry(*id))
+                .collect(),
+            rejected_actions,
+        }))
+    }
+}
+
+impl StratSyncService {
+    async fn fetch_remote_source(
+        &self,
+        session: &Session,
+        source_player_id: Uuid,
+    ) -> Result<Option<(Option<String>, Vec<EntryState>)>, Status> {
+        let peer_context = self.session_peer_context(session)?;
+
+        let row = sqlx::query!(
+            r#"SELECT p.job AS "job: String", p.strategy, s.raid, s.author, s.is_public
+                 FROM public.strategy_players AS p
+                      JOIN public.strategies AS s
+                      ON p.strategy = s.id
+                WHERE p.id = $1"#,
+            source_player_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| Status::failed_precondition("Source player not found"))?;
+
+        if row.strategy == peer_context.strategy_id {
+            return Ok(None);
+        }
+
+        let user_id = session.user_id;
+
+        let role = match user_id {
+            Some(user_id) => self.fetch_collaborator_role(row.strategy, user_id).await,
+            None => None,
+        };
+
+        let is_author = user_id.is_some() && user_id == row.author;
+
+        if !row.is_public && !is_author && role.is_none() {
+            return Err(Status::permission_denied("Access denied to strategy"));
+        }
+
+        if row.raid != peer_context.raid_id {
+            return Err(Status::failed_precondition(
+                "Source strategy belongs to a different raid",
+            ));
+        }
+
+        // An open strategy may have changes that are not written back yet.
+        if let Some(strategy) = self.strategies.get(&row.strategy) {
+            if let Some(source_context) = strategy.acquire().await {
+                let source_job = source_context
+                    .players
+                    .iter()
+                    .find(|player| player.id == source_player_id.to_string())
+                    .and_then(|player| player.job.clone());
+
+                return Ok(Some((
+                    source_job,
+                    player_entries(&source_context, source_player_id),
+                )));
+            }
+        }
+
+        let source_entries = sqlx::query_as!(
+            EntryState,
+            r#"SELECT player, action, use_at
+                 FROM public.strategy_player_entries
+                WHERE player = $1"#,
+            source_player_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap();
+
+        Ok(Some((row.job, source_entries)))
+    }
+}
+
+fn player_entries(strategy_context: &StrategyContext, player_id: Uuid) -> Vec<EntryState> {
+    strategy_context
+        .entries
+        .ids_of_player(player_id)
+        .iter()
+        .map(|id| *strategy_context.entries.get(id).unwrap())
+        .collect()
+}
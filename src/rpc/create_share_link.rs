@@ -0,0 +1,75 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+const MAX_SHARE_LINK_TTL: i32 = 30 * 24 * 60 * 60; // 30 days
+
+impl StratSyncService {
+    pub async fn rpc_create_share_link(
+        &self,
+        request: Request<CreateShareLinkRequest>,
+    ) -> Result<Response<CreateShareLinkResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            _strategy_context
+        );
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can create share links",
+            ));
+        }
+
+        let scope = ShareScope::from_str(&payload.scope)
+            .map_err(|_| Status::invalid_argument("Invalid scope"))?;
+
+        if payload.expires_in <= 0 || payload.expires_in > MAX_SHARE_LINK_TTL {
+            return Err(Status::invalid_argument("expires_in is out of range"));
+        }
+
+        let link_id = Uuid::new_v4();
+
+        let expires_at = sqlx::query!(
+            r#"INSERT INTO public.strategy_share_links (id, strategy, scope, expires_at)
+                    VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+                 RETURNING EXTRACT(EPOCH FROM expires_at)::bigint AS "expires_at!""#,
+            link_id,
+            peer_context.strategy_id,
+            scope as ShareScope,
+            payload.expires_in as f64,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap()
+        .expires_at;
+
+        let share_token = utils::encode_share_token(
+            link_id,
+            peer_context.strategy_id,
+            scope,
+            Duration::from_secs(payload.expires_in as u64),
+        );
+
+        Ok(Response::new(CreateShareLinkResponse {
+            share_link: Some(ShareLink {
+                id: link_id.to_string(),
+                scope: scope.to_string(),
+                expires_at,
+            }),
+            share_token,
+        }))
+    }
+}
@@ -9,11 +9,12 @@ impl StratSyncService {
         &self,
         request: Request<DeleteNoteRequest>,
     ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
-        utils::open_strategy_elevated!(
+        utils::open_strategy_notes_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
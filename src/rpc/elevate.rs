@@ -1,31 +1,89 @@
-use std::sync::Arc;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
 
 use crate::protos::stratsync::*;
 use crate::types::*;
 use crate::utils;
 
-use tonic::{Request, Response, Status};
+use moka::sync::Cache;
+use tonic::{metadata::MetadataValue, Request, Response, Status};
+
+const FREE_ATTEMPTS_PER_ADDRESS: u32 = 5;
+const FREE_ATTEMPTS_PER_STRATEGY: u32 = 20;
+const BASE_LOCKOUT: Duration = Duration::from_secs(2);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60); // 15 minutes
+
+fn check_lockout<K: Hash + Eq + Send + Sync + 'static>(
+    attempts: &Cache<K, ElevationAttempts>,
+    key: &K,
+) -> Result<(), Status> {
+    let locked_until = match attempts.get(key).and_then(|attempts| attempts.locked_until) {
+        Some(locked_until) => locked_until,
+        None => return Ok(()),
+    };
+
+    let now = Instant::now();
+    if locked_until <= now {
+        return Ok(());
+    }
+
+    let retry_after = (locked_until - now).as_secs() + 1;
+    let mut status = Status::resource_exhausted(format!(
+        "Too many failed attempts, retry after {} seconds",
+        retry_after
+    ));
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(retry_after));
+
+    Err(status)
+}
+
+fn record_failure<K: Hash + Eq + Send + Sync + 'static>(
+    attempts: &Cache<K, ElevationAttempts>,
+    key: K,
+    free_attempts: u32,
+) -> u32 {
+    attempts
+        .entry(key)
+        .and_upsert_with(|entry| {
+            let mut attempts_after = entry.map(|entry| entry.into_value()).unwrap_or_default();
+            attempts_after.failures += 1;
+
+            if attempts_after.failures > free_attempts {
+                let exponent = (attempts_after.failures - free_attempts - 1).min(16);
+                let lockout = (BASE_LOCKOUT * 2u32.pow(exponent)).min(MAX_LOCKOUT);
+                attempts_after.locked_until = Some(Instant::now() + lockout);
+            }
+
+            attempts_after
+        })
+        .into_value()
+        .failures
+}
 
 impl StratSyncService {
     pub async fn rpc_elevate(
         &self,
         request: Request<ElevationRequest>,
     ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
-        utils::open_strategy!(
-            self,
-            &payload.token,
-            peer_context,
-            lock,
-            _guard,
-            strategy_context
-        );
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
 
-        if strategy_context.elevated_peers.contains(&payload.token) {
+        if strategy_context.elevated_peers.contains(&session.token) {
             return Err(Status::failed_precondition("Already elevated"));
         }
 
+        if !peer_context.is_author
+            && self.is_elevation_banned(peer_context.strategy_id, session.user_id, session.address)
+        {
+            return Err(Status::permission_denied(
+                "Elevation has been disabled for this client",
+            ));
+        }
+
         let row = sqlx::query!(
             r#"SELECT password, is_editable
                  FROM public.strategies
@@ -37,6 +95,42 @@ impl StratSyncService {
         .unwrap();
         let is_strategy_editable = row.is_editable;
 
+        if let Some(share_token) = payload.share_token.as_deref() {
+            let (link_id, scope) = self
+                .verify_share_token(share_token, peer_context.strategy_id)
+                .await?;
+
+            match scope {
+                ShareScope::View => {
+                    return Err(Status::permission_denied(
+                        "Share link does not grant edit access",
+                    ));
+                }
+                ShareScope::Edit => {
+                    if !is_strategy_editable {
+                        return Err(Status::permission_denied("Strategy is not editable"));
+                    }
+
+                    strategy_context
+                        .note_peers
+                        .retain(|peer| *peer != session.token);
+                    strategy_context.elevated_peers.push(session.token.clone());
+                }
+                ShareScope::Notes => {
+                    if strategy_context.note_peers.contains(&session.token) {
+                        return Err(Status::failed_precondition("Already elevated"));
+                    }
+
+                    strategy_context.note_peers.push(session.token.clone());
+                }
+            }
+            strategy_context
+                .share_link_peers
+                .insert(session.token.clone(), link_id);
+
+            return Ok(Response::new(()));
+        }
+
         if !is_strategy_editable {
             return Err(Status::permission_denied("Strategy is not editable"));
         }
@@ -45,16 +139,47 @@ impl StratSyncService {
             .password
             .ok_or_else(|| Status::permission_denied("Strategy password is not set"))?;
 
+        check_lockout(&self.strategy_elevation_attempts, &peer_context.strategy_id)?;
+        if let Some(address) = session.address {
+            check_lockout(&self.address_elevation_attempts, &address)?;
+        }
+
         if !bcrypt::verify(payload.password.as_str(), strategy_password.as_str()).unwrap_or(false) {
+            let strategy_failures = record_failure(
+                &self.strategy_elevation_attempts,
+                peer_context.strategy_id,
+                FREE_ATTEMPTS_PER_STRATEGY,
+            );
+            let address_failures = session.address.map(|address| {
+                record_failure(
+                    &self.address_elevation_attempts,
+                    address,
+                    FREE_ATTEMPTS_PER_ADDRESS,
+                )
+            });
+
+            tracing::warn!(
+                target: "security",
+                strategy = %peer_context.strategy_id,
+                address = ?session.address,
+                strategy_failures,
+                address_failures,
+                "Failed elevation attempt"
+            );
+
             return Err(Status::permission_denied("Invalid password"));
         }
 
-        let mut strategy_context_after = (*strategy_context).to_owned();
-        strategy_context_after
-            .elevated_peers
-            .push(payload.token.clone());
-        self.strategy_context
-            .insert(peer_context.strategy_id, Arc::new(strategy_context_after));
+        // The address counter is kept, as knowing the password of one strategy
+        // must not reset the lockout for guesses on others.
+        self.strategy_elevation_attempts
+            .invalidate(&peer_context.strategy_id);
+
+        strategy_context
+            .note_peers
+            .retain(|peer| *peer != session.token);
+        strategy_context.share_link_peers.remove(&session.token);
+        strategy_context.elevated_peers.push(session.token.clone());
 
         Ok(Response::new(()))
     }
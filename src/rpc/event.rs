@@ -1,26 +1,31 @@
 use crate::protos::stratsync::*;
+use crate::strategy::StrategyHandle;
 use crate::types::*;
 use crate::utils;
 
 use sqlx::types::Uuid;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
+const HUB_CAPACITY: usize = 256;
+
 impl StratSyncService {
     pub async fn rpc_event(
         &self,
         request: Request<SubscriptionRequest>,
     ) -> Result<Response<ReceiverStream<Result<EventResponse, Status>>>, Status> {
         let metadata = request.metadata().to_owned();
+        let address = request.remote_addr().map(|addr| addr.ip());
         let payload = request.into_inner();
 
         let strategy_id =
             utils::parse_string_to_uuid(&payload.strategy, "Strategy id has an invalid format")?;
 
         let row = sqlx::query!(
-            r#"SELECT raid, author, is_public
+            r#"SELECT raid, author, is_public, is_editable
                  FROM public.strategies
                 WHERE id = $1"#,
             strategy_id
@@ -31,160 +36,177 @@ impl StratSyncService {
 
         let raid_id = row.raid;
 
-        let is_author = utils::parse_authorization_header(&metadata)?
-            .map(|user_id| Some(user_id) == row.author)
-            .unwrap_or(false);
+        let user_id = utils::parse_authorization_header(&metadata).await?;
 
-        if !row.is_public && !is_author {
-            return Err(Status::permission_denied("Access denied to strategy"));
-        }
+        let role = match user_id {
+            Some(user_id) => self.fetch_collaborator_role(strategy_id, user_id).await,
+            None => None,
+        };
 
-        if !self.raid_cache.contains_key(&raid_id) {
-            let (damages, row) = tokio::try_join!(
-                sqlx::query_as!(
-                    Damage,
-                    r#"SELECT d.id, max_shared, num_targets
-                         FROM public.damages AS d
-                              JOIN public.gimmicks AS g
-                              ON d.gimmick = g.id
-                        WHERE g.raid = $1"#,
-                    raid_id
-                )
-                .fetch_all(&self.pool),
-                sqlx::query!(
-                    r#"SELECT duration, headcount
-                         FROM public.raids
-                        WHERE id = $1"#,
-                    raid_id
-                )
-                .fetch_one(&self.pool),
-            )
-            .unwrap();
-
-            self.raid_cache.insert(
-                raid_id,
-                Arc::new(RaidInfo {
-                    duration: row.duration,
-                    headcount: row.headcount,
-                    damages,
-                }),
-            );
-        }
+        let is_author =
+            (user_id.is_some() && user_id == row.author) || role == Some(CollaboratorRole::Owner);
 
-        let lock = if let Some(lock) = self.strategy_lock.get(&strategy_id) {
-            lock
-        } else {
-            let lock = Arc::new(Mutex::new(()));
-            self.strategy_lock.insert(strategy_id, lock.clone());
-            lock
+        let share_link = match payload.share_token.as_deref() {
+            Some(share_token) => Some(self.verify_share_token(share_token, strategy_id).await?),
+            None => None,
         };
-        let _guard = lock.lock().await;
 
-        let token = Uuid::new_v4().to_string();
+        if !row.is_public && !is_author && role.is_none() && share_link.is_none() {
+            return Err(Status::permission_denied("Access denied to strategy"));
+        }
 
-        let peers: Vec<String> = match self.strategy_context.get(&strategy_id) {
-            Some(strategy_context) => strategy_context
-                .peers
-                .iter()
-                .chain([token.clone()].iter())
-                .map(|el| el.to_owned())
-                .collect(),
-            None => vec![token.clone()],
+        let is_elevation_banned =
+            !is_author && self.is_elevation_banned(strategy_id, user_id, address);
+        let share_link = share_link.filter(|_| !is_elevation_banned);
+        let share_scope = share_link.map(|(_, scope)| scope);
+
+        // Violations are checked when the strategy is loaded, against the
+        // current catalog, and the report is kept for later subscribers.
+        let mut notes = None;
+
+        // A strategy that is closing refuses new leases, in which case it is
+        // reopened from the database.
+        let mut guard = loop {
+            let strategy = match self.strategies.get(&strategy_id) {
+                Some(strategy) => strategy,
+                None => {
+                    self.reload_catalog(raid_id).await?;
+                    notes = Some(self.fetch_notes(strategy_id).await?);
+
+                    let (players, damage_options, entries) = tokio::try_join!(
+                        sqlx::query_as!(
+                            Player,
+                            r#"  WITH ordered_table AS (SELECT *
+                                                        FROM public.strategy_players
+                                                        ORDER BY "order")
+                               SELECT id, job AS "job: String", "order"
+                                 FROM ordered_table
+                                WHERE strategy = $1"#,
+                            strategy_id
+                        )
+                        .fetch_all(&self.pool),
+                        sqlx::query!(
+                            r#"SELECT damage, num_shared, primary_target
+                                 FROM public.strategy_damage_options
+                                WHERE strategy = $1"#,
+                            strategy_id
+                        )
+                        .fetch_all(&self.pool),
+                        sqlx::query!(
+                            r#"SELECT e.id, player, action, use_at
+                                 FROM public.strategy_player_entries AS e
+                                      JOIN public.strategy_players AS p
+                                      ON e.player = p.id
+                                WHERE p.strategy = $1"#,
+                            strategy_id
+                        )
+                        .fetch_all(&self.pool),
+                    )
+                    .unwrap();
+
+                    let damage_options = damage_options
+                        .iter()
+                        .map(|record| DamageOption {
+                            damage: record.damage.to_string(),
+                            num_shared: record.num_shared,
+                            primary_target: record.primary_target.map(|s| s.to_string()),
+                        })
+                        .collect();
+
+                    let mut entry_index = EntryIndex::default();
+                    for record in entries {
+                        entry_index.insert(
+                            record.id,
+                            EntryState {
+                                player: record.player,
+                                action: record.action,
+                                use_at: record.use_at,
+                            },
+                        );
+                    }
+
+                    let (hub, _) = broadcast::channel(HUB_CAPACITY);
+
+                    self.strategies
+                        .entry(strategy_id)
+                        .or_insert_with(|| {
+                            StrategyHandle::spawn(
+                                strategy_id,
+                                StrategyContext {
+                                    raid_id,
+                                    revision: 0,
+                                    hub,
+                                    peers: vec![],
+                                    elevated_peers: vec![],
+                                    note_peers: vec![],
+                                    share_link_peers: HashMap::new(),
+                                    share_link_viewers: HashMap::new(),
+                                    players,
+                                    damage_options,
+                                    entries: entry_index,
+                                    chat_history: VecDeque::new(),
+                                    write_behind: self.write_behind.then(Default::default),
+                                    entry_events: PendingEntryEvents::default(),
+                                    idempotency_keys: HashMap::new(),
+                                    violations: vec![],
+                                },
+                                self.pool.clone(),
+                                self.strategies.clone(),
+                                self.peer_context.clone(),
+                            )
+                        })
+                        .into_value()
+                }
+            };
+
+            if let Some(lease) = strategy.acquire().await {
+                break lease;
+            }
         };
+        let strategy_context = &mut *guard;
+
+        if let Some(notes) = notes {
+            strategy_context.violations = self.validate_strategy(strategy_context, &notes);
+        }
 
-        let elevated_peers: Vec<String> = match self.strategy_context.get(&strategy_id) {
-            Some(strategy_context) => strategy_context.elevated_peers.clone(),
-            None => vec![],
+        let token = Uuid::new_v4().to_string();
+        let peer_id = Uuid::new_v4();
+
+        strategy_context.peers.push(token.clone());
+        if is_author
+            || (role == Some(CollaboratorRole::Editor) && !is_elevation_banned && row.is_editable)
+            || (share_scope == Some(ShareScope::Edit) && row.is_editable)
+        {
+            strategy_context.elevated_peers.push(token.clone());
+        }
+        if share_scope == Some(ShareScope::Notes) {
+            strategy_context.note_peers.push(token.clone());
         }
-        .iter()
-        .chain(
-            if is_author {
-                vec![token.clone()]
-            } else {
-                vec![]
+        if let Some((link_id, scope)) = share_link {
+            if scope != ShareScope::View {
+                strategy_context
+                    .share_link_peers
+                    .insert(token.clone(), link_id);
+            }
+            // Peers that can only read the strategy through the link are kicked
+            // when it is revoked.
+            if !row.is_public && !is_author && role.is_none() {
+                strategy_context
+                    .share_link_viewers
+                    .insert(token.clone(), link_id);
             }
-            .iter(),
-        )
-        .map(|el| el.to_owned())
-        .collect();
-
-        let players: Vec<Player>;
-        let damage_options: Vec<DamageOption>;
-        let entries: Vec<Entry>;
-        if peers.len() > 1 {
-            let mut strategy_context =
-                (*self.strategy_context.get(&strategy_id).unwrap()).to_owned();
-            strategy_context.peers = peers;
-            strategy_context.elevated_peers = elevated_peers;
-
-            players = strategy_context.players.clone();
-            damage_options = strategy_context.damage_options.clone();
-            entries = strategy_context.entries.clone();
-
-            self.strategy_context
-                .insert(strategy_id, Arc::new(strategy_context))
-        } else {
-            let damage_options_raw: Vec<_>;
-            (players, damage_options_raw, entries) = tokio::try_join!(
-                sqlx::query_as!(
-                    Player,
-                    r#"  WITH ordered_table AS (SELECT *
-                                                FROM public.strategy_players
-                                                ORDER BY "order")
-                       SELECT id, job AS "job: String", "order"
-                         FROM ordered_table
-                        WHERE strategy = $1"#,
-                    strategy_id
-                )
-                .fetch_all(&self.pool),
-                sqlx::query!(
-                    r#"SELECT damage, num_shared, primary_target
-                         FROM public.strategy_damage_options
-                        WHERE strategy = $1"#,
-                    strategy_id
-                )
-                .fetch_all(&self.pool),
-                sqlx::query_as!(
-                    Entry,
-                    r#"SELECT e.id AS id, player, action, use_at
-                         FROM public.strategy_player_entries AS e
-                              JOIN public.strategy_players AS p
-                              ON e.player = p.id
-                        WHERE p.strategy = $1"#,
-                    strategy_id
-                )
-                .fetch_all(&self.pool),
-            )
-            .unwrap();
-
-            damage_options = damage_options_raw
-                .iter()
-                .map(|record| DamageOption {
-                    damage: record.damage.to_string(),
-                    num_shared: record.num_shared,
-                    primary_target: record.primary_target.map(|s| s.to_string()),
-                })
-                .collect();
-
-            self.strategy_context.insert(
-                strategy_id,
-                Arc::new(StrategyContext {
-                    raid_id,
-                    peers,
-                    elevated_peers,
-                    players: players.clone(),
-                    damage_options: damage_options.clone(),
-                    entries: entries.clone(),
-                }),
-            );
         }
 
         let (tx, rx) = mpsc::channel(32);
+        let hub_rx = strategy_context.hub.subscribe();
         self.peer_context.insert(
             token.clone(),
             Arc::new(PeerContext {
+                peer_id,
                 strategy_id,
                 raid_id,
+                address,
+                user_id,
                 is_author,
                 tx: tx.clone(),
             }),
@@ -193,16 +215,22 @@ impl StratSyncService {
         tx.send(Ok(EventResponse {
             event: Some(event_response::Event::InitializationEvent(
                 InitializationEvent {
-                    token,
-                    players,
-                    damage_options,
-                    entries,
+                    token: token.clone(),
+                    players: strategy_context.players.clone(),
+                    damage_options: strategy_context.damage_options.clone(),
+                    entries: strategy_context.entries.to_entries(),
+                    peer_id: peer_id.to_string(),
+                    chat_messages: strategy_context.chat_history.iter().cloned().collect(),
+                    revision: strategy_context.revision,
+                    violations: strategy_context.violations.clone(),
                 },
             )),
         }))
         .await
         .unwrap();
 
+        utils::spawn_hub_forwarder(token, &tx, hub_rx);
+
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 }
@@ -0,0 +1,69 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_get_availability(
+        &self,
+        request: Request<GetAvailabilityRequest>,
+    ) -> Result<Response<GetAvailabilityResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        let player_filter = match payload.player.as_deref() {
+            Some(player) => Some(utils::parse_string_to_uuid(
+                player,
+                "player has an invalid format",
+            )?),
+            None => None,
+        };
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        let raid = self.raid_cache.get(&strategy_context.raid_id).unwrap();
+
+        let players: Vec<(Uuid, &Player)> = strategy_context
+            .players
+            .iter()
+            .map(|player| (Uuid::parse_str(&player.id).unwrap(), player))
+            .filter(|(player_id, _)| player_filter.is_none_or(|filter| filter == *player_id))
+            .collect();
+
+        if player_filter.is_some() && players.is_empty() {
+            return Err(Status::failed_precondition("Player not found"));
+        }
+
+        let mut availabilities = Vec::new();
+        for (player_id, player, job) in players
+            .into_iter()
+            .filter_map(|(player_id, player)| Some((player_id, player, player.job.as_ref()?)))
+        {
+            for action in self.job_actions(job).iter() {
+                let intervals = utils::available_use_ats(
+                    strategy_context
+                        .entries
+                        .column(player_id, action.id)
+                        .map(|(_, use_at)| use_at),
+                    action.cooldown,
+                    action.charges,
+                    -MAX_COUNTDOWN,
+                    raid.duration,
+                )
+                .into_iter()
+                .map(|(from, to)| AvailabilityInterval { from, to })
+                .collect();
+
+                availabilities.push(ActionAvailability {
+                    player: player.id.clone(),
+                    action: action.id.to_string(),
+                    intervals,
+                });
+            }
+        }
+
+        Ok(Response::new(GetAvailabilityResponse { availabilities }))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+const MAX_KICK_REASON_LENGTH: usize = 128;
+
+impl StratSyncService {
+    pub async fn rpc_kick_peer(
+        &self,
+        request: Request<KickPeerRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied("Only the author can kick peers"));
+        }
+
+        let peer_id = utils::parse_string_to_uuid(&payload.peer, "Peer id has an invalid format")?;
+
+        if payload
+            .reason
+            .as_ref()
+            .is_some_and(|reason| reason.len() > MAX_KICK_REASON_LENGTH)
+        {
+            return Err(Status::invalid_argument("Reason is too long"));
+        }
+
+        let (target, target_context) = strategy_context
+            .peers
+            .iter()
+            .filter_map(|peer| {
+                self.peer_context
+                    .get(peer)
+                    .map(|peer_context| (peer, peer_context))
+            })
+            .find(|(_, peer_context)| peer_context.peer_id == peer_id)
+            .ok_or_else(|| Status::failed_precondition("Peer not found"))?;
+
+        if *target == session.token {
+            return Err(Status::invalid_argument("Cannot kick yourself"));
+        }
+
+        if payload.ban {
+            if target_context.is_author {
+                return Err(Status::failed_precondition("Cannot ban the author"));
+            }
+
+            let mut bans = self
+                .elevation_bans
+                .get(&peer_context.strategy_id)
+                .unwrap_or_default();
+            if let Some(user_id) = target_context.user_id {
+                bans.users.push(user_id);
+            }
+            if let Some(address) = target_context.address {
+                bans.addresses.push(address);
+            }
+            self.elevation_bans.insert(peer_context.strategy_id, bans);
+        }
+
+        self.kick(target, payload.reason);
+
+        Ok(Response::new(()))
+    }
+}
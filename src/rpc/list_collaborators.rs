@@ -0,0 +1,47 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_list_collaborators(
+        &self,
+        request: Request<ListCollaboratorsRequest>,
+    ) -> Result<Response<ListCollaboratorsResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+
+        utils::open_strategy!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            _strategy_context
+        );
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can manage collaborators",
+            ));
+        }
+
+        let collaborators = sqlx::query!(
+            r#"SELECT "user", role AS "role: CollaboratorRole"
+                 FROM public.strategy_collaborators
+                WHERE strategy = $1"#,
+            peer_context.strategy_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| Collaborator {
+            user: row.user.to_string(),
+            role: row.role.to_string(),
+        })
+        .collect();
+
+        Ok(Response::new(ListCollaboratorsResponse { collaborators }))
+    }
+}
@@ -0,0 +1,30 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_list_peers(
+        &self,
+        request: Request<ListPeersRequest>,
+    ) -> Result<Response<ListPeersResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        let peers = strategy_context
+            .peers
+            .iter()
+            .filter_map(|peer| {
+                self.peer_context.get(peer).map(|peer_context| PeerInfo {
+                    id: peer_context.peer_id.to_string(),
+                    permission: utils::permission_of(peer, strategy_context).to_string(),
+                    is_author: peer_context.is_author,
+                })
+            })
+            .collect();
+
+        Ok(Response::new(ListPeersResponse { peers }))
+    }
+}
@@ -0,0 +1,52 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_list_share_links(
+        &self,
+        request: Request<ListShareLinksRequest>,
+    ) -> Result<Response<ListShareLinksResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+
+        utils::open_strategy!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            _strategy_context
+        );
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can list share links",
+            ));
+        }
+
+        let share_links = sqlx::query!(
+            r#"SELECT id, scope AS "scope: ShareScope",
+                      EXTRACT(EPOCH FROM expires_at)::bigint AS "expires_at!"
+                 FROM public.strategy_share_links
+                WHERE strategy = $1
+                  AND NOT revoked
+                  AND expires_at > now()
+                ORDER BY expires_at"#,
+            peer_context.strategy_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| ShareLink {
+            id: row.id.to_string(),
+            scope: row.scope.to_string(),
+            expires_at: row.expires_at,
+        })
+        .collect();
+
+        Ok(Response::new(ListShareLinksResponse { share_links }))
+    }
+}
@@ -1,8 +1,28 @@
 mod clear_other_sessions;
+mod copy_entries;
+mod create_share_link;
 mod delete_note;
 mod elevate;
 mod event;
+mod get_availability;
+mod kick_peer;
+mod list_collaborators;
+mod list_peers;
+mod list_share_links;
 mod mutate_entries;
+mod ping;
+mod remove_collaborator;
+mod reorder_players;
+mod revoke_share_link;
+mod send_chat_message;
+mod set_editable;
+mod set_strategy_password;
+mod shift_entries;
+mod suggest_plan;
+mod swap_players;
+mod transfer_ownership;
 mod update_player_job;
+mod upsert_collaborator;
 mod upsert_damage_option;
 mod upsert_note;
+mod validate_strategy;
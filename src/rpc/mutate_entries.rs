@@ -1,5 +1,4 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
 
 use crate::protos::stratsync::*;
 use crate::types::*;
@@ -12,18 +11,21 @@ impl StratSyncService {
     pub async fn rpc_mutate_entries(
         &self,
         request: Request<MutateEntriesRequest>,
-    ) -> Result<Response<()>, Status> {
+    ) -> Result<Response<MutateEntriesResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
         utils::open_strategy_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
             strategy_context
         );
 
+        strategy_context.check_pending_writes()?;
+
         let raid = self.raid_cache.get(&strategy_context.raid_id).unwrap();
 
         let player_lookup: HashMap<Uuid, &Player> = strategy_context
@@ -36,11 +38,35 @@ impl StratSyncService {
             .values()
             .filter_map(|player| player.job.as_ref())
         {
-            for action in self.action_cache.get(job).unwrap().iter() {
+            for action in self.job_actions(job).iter() {
                 action_lookup.insert(action.id, action.clone());
             }
         }
 
+        // Items whose entry no longer has the use_at the client based its edit on
+        // are left untouched and reported back with the current value.
+        let mut conflicts: Vec<EntryConflict> = Vec::new();
+        let mut conflicting_ids: HashSet<Uuid> = HashSet::new();
+        for expected in &payload.expected {
+            let id = utils::parse_string_to_uuid(&expected.id, "id has an invalid format")?;
+            let current = strategy_context.entries.get(&id);
+
+            if current.is_some_and(|entry| entry.use_at == expected.use_at) {
+                continue;
+            }
+
+            let reason = match current {
+                Some(_) => "Entry has changed",
+                None => "Entry has been deleted",
+            };
+            conflicts.push(EntryConflict {
+                id: id.to_string(),
+                reason: reason.to_string(),
+                current: current.map(|entry| entry.to_entry(id)),
+            });
+            conflicting_ids.insert(id);
+        }
+
         let mut grouped_upserts: HashMap<(Uuid, Uuid), Vec<(Uuid, i32)>> = HashMap::new();
         for entry in &payload.upserts {
             let id = utils::parse_string_to_uuid(&entry.id, "id has an invalid format")?;
@@ -64,143 +90,127 @@ impl StratSyncService {
                 ));
             }
 
+            if conflicting_ids.contains(&id) {
+                continue;
+            }
+
             grouped_upserts
                 .entry((player_id, action_id))
                 .or_default()
                 .push((id, use_at));
         }
 
-        let mut entries_after = strategy_context.entries.clone();
         let mut accepted_deletes: Vec<Uuid> = Vec::new();
         let mut accepted_upserts: Vec<(Uuid, Uuid, Uuid, i32)> = Vec::new();
-        let mut rejected_upserts: Vec<(Uuid, Uuid, Uuid, i32)> = Vec::new();
+        let mut rejected_upserts: Vec<Uuid> = Vec::new();
+        let mut placements: Vec<EntryPlacement> = Vec::new();
 
-        for id in &payload.deletes {
-            if let Some(entry) = strategy_context
-                .entries
-                .iter()
-                .find(|entry| entry.id == *id)
-            {
-                if match grouped_upserts.get(&(
-                    Uuid::parse_str(&entry.player).unwrap(),
-                    Uuid::parse_str(&entry.action).unwrap(),
-                )) {
-                    Some(upserts) => upserts
-                        .iter()
-                        .any(|(upsert_id, _)| upsert_id.to_string() == *id),
-                    None => false,
-                } {
+        for id in payload
+            .deletes
+            .iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .filter(|id| !conflicting_ids.contains(id))
+        {
+            if let Some(entry) = strategy_context.entries.get(&id) {
+                if grouped_upserts
+                    .get(&(entry.player, entry.action))
+                    .is_some_and(|upserts| upserts.iter().any(|&(upsert_id, _)| upsert_id == id))
+                {
                     return Err(Status::invalid_argument(
                         "Cannot delete an entry that is being upserted",
                     ));
                 }
 
-                accepted_deletes.push(Uuid::parse_str(id).unwrap());
+                accepted_deletes.push(id);
             }
         }
 
-        entries_after
-            .retain(|entry| !accepted_deletes.contains(&Uuid::parse_str(&entry.id).unwrap()));
-
-        let keys_to_check: HashSet<_> = grouped_upserts
-            .keys()
-            .map(|(player_id, action_id)| (player_id.to_owned(), action_id.to_owned()))
-            .collect();
+        for id in &accepted_deletes {
+            strategy_context.entries.remove(id);
+        }
 
-        for (player_id, action_id) in keys_to_check {
+        for ((player_id, action_id), upserts_col) in grouped_upserts {
             let action = action_lookup.get(&action_id).unwrap();
 
-            let entries_col: Vec<_> = entries_after
-                .iter()
-                .filter(|entry| {
-                    entry.player == player_id.to_string() && entry.action == action_id.to_string()
-                })
-                .cloned()
-                .collect();
-
-            let upserts_col = grouped_upserts.get(&(player_id, action_id));
-
-            let mut use_at_prov_map: HashMap<Uuid, i32> = entries_col
-                .into_iter()
-                .map(|entry| (Uuid::parse_str(&entry.id).unwrap(), entry.use_at))
+            let mut use_at_prov_map: HashMap<Uuid, i32> = strategy_context
+                .entries
+                .column(player_id, action_id)
                 .collect();
+            use_at_prov_map.extend(upserts_col.iter().copied());
 
-            if let Some(upserts_col) = upserts_col {
-                use_at_prov_map.extend(upserts_col.iter().cloned());
-            }
-
-            let mut col_sweeping: Vec<(i32, i32)> = Vec::new();
-            for use_at in use_at_prov_map.values() {
-                col_sweeping.push((*use_at, 1));
-                col_sweeping.push((*use_at + action.cooldown, -1));
-            }
-            col_sweeping.sort();
-
-            let mut max_simultaneous_uses = 0;
-            let mut current_uses = 0;
-            for (_, delta) in col_sweeping {
-                current_uses += delta;
-                max_simultaneous_uses = max_simultaneous_uses.max(current_uses);
-            }
+            let max_simultaneous_uses =
+                utils::max_simultaneous_uses(use_at_prov_map.values().copied(), action.cooldown);
 
             if max_simultaneous_uses <= action.charges {
-                if let Some(upserts_col) = upserts_col {
-                    accepted_upserts.extend(
-                        upserts_col
-                            .iter()
-                            .map(|&(id, use_at)| (player_id, action_id, id, use_at)),
+                for &(id, use_at) in &upserts_col {
+                    strategy_context.entries.insert(
+                        id,
+                        EntryState {
+                            player: player_id,
+                            action: action_id,
+                            use_at,
+                        },
                     );
+                    accepted_upserts.push((player_id, action_id, id, use_at));
                 }
+            } else {
+                // Each suggestion has to fit next to the other upserts of the group,
+                // which replace their stored entries.
+                let group_ids: HashSet<Uuid> = upserts_col.iter().map(|&(id, _)| id).collect();
+
+                for &(id, use_at) in &upserts_col {
+                    let available = utils::available_use_ats(
+                        strategy_context
+                            .entries
+                            .column(player_id, action_id)
+                            .filter(|(entry_id, _)| !group_ids.contains(entry_id))
+                            .map(|(_, use_at)| use_at)
+                            .chain(
+                                upserts_col
+                                    .iter()
+                                    .filter(|&&(other_id, _)| other_id != id)
+                                    .map(|&(_, use_at)| use_at),
+                            ),
+                        action.cooldown,
+                        action.charges,
+                        -MAX_COUNTDOWN,
+                        raid.duration,
+                    );
 
-                entries_after.retain(|entry| {
-                    entry.player != player_id.to_string() || entry.action != action_id.to_string()
-                });
-
-                for (id, use_at) in use_at_prov_map {
-                    entries_after.push(Entry {
+                    placements.push(EntryPlacement {
                         id: id.to_string(),
-                        player: player_id.to_string(),
-                        action: action_id.to_string(),
-                        use_at,
+                        earlier_use_at: available
+                            .iter()
+                            .rev()
+                            .find(|&&(from, _)| from <= use_at)
+                            .map(|&(_, to)| to.min(use_at)),
+                        later_use_at: available
+                            .iter()
+                            .find(|&&(_, to)| to >= use_at)
+                            .map(|&(from, _)| from.max(use_at)),
                     });
                 }
-            } else if let Some(upserts_col) = upserts_col {
-                rejected_upserts.extend(
-                    upserts_col
-                        .iter()
-                        .map(|&(id, use_at)| (player_id, action_id, id, use_at)),
-                );
+
+                rejected_upserts.extend(upserts_col.iter().map(|&(id, _)| id));
             }
         }
 
-        let mut strategy_context_after = (*strategy_context).to_owned();
-        strategy_context_after.entries = entries_after;
-        self.strategy_context
-            .insert(peer_context.strategy_id, Arc::new(strategy_context_after));
+        if !accepted_deletes.is_empty() || !accepted_upserts.is_empty() {
+            strategy_context.revision += 1;
+        }
 
         if !rejected_upserts.is_empty() {
-            let current_entries_map: HashMap<String, i32> = strategy_context
-                .entries
-                .iter()
-                .map(|entry| (entry.id.to_owned(), entry.use_at))
-                .collect();
-
             let (entries_present, entries_not_present): (Vec<_>, Vec<_>) = rejected_upserts
                 .into_iter()
-                .partition(|(_, _, id, _)| current_entries_map.contains_key(&id.to_string()));
+                .partition(|id| strategy_context.entries.contains(id));
 
             let upserts_self: Vec<Entry> = entries_present
                 .into_iter()
-                .map(|(player_id, action_id, id, _)| Entry {
-                    id: id.to_string(),
-                    player: player_id.to_string(),
-                    action: action_id.to_string(),
-                    use_at: current_entries_map[&id.to_string()],
-                })
+                .map(|id| strategy_context.entries.get(&id).unwrap().to_entry(id))
                 .collect();
             let deletes_self: Vec<String> = entries_not_present
                 .into_iter()
-                .map(|(_, _, id, _)| id.to_string())
+                .map(|id| id.to_string())
                 .collect();
 
             if !upserts_self.is_empty() || !deletes_self.is_empty() {
@@ -209,106 +219,37 @@ impl StratSyncService {
                     deletes: deletes_self,
                 });
 
-                peer_context
-                    .tx
-                    .send(Ok(EventResponse { event: Some(event) }))
-                    .await
-                    .unwrap();
+                self.send_or_kick(&session.token, event);
             }
         }
 
-        let delete_query = if !accepted_deletes.is_empty() {
-            let query = sqlx::query!(
-                r#"DELETE FROM public.strategy_player_entries
-                         WHERE id = ANY($1)"#,
-                &accepted_deletes
-            )
-            .execute(&self.pool);
-
-            Some(query)
-        } else {
-            None
-        };
-
-        let upsert_query = if !accepted_upserts.is_empty() {
-            let (player_vec, action_vec, id_vec, use_at_vec) = accepted_upserts.iter().fold(
-                (Vec::new(), Vec::new(), Vec::new(), Vec::new()),
-                |(mut player_vec, mut action_vec, mut id_vec, mut use_at_vec),
-                 &(player, action, id, use_at)| {
-                    player_vec.push(player);
-                    action_vec.push(action);
-                    id_vec.push(id);
-                    use_at_vec.push(use_at);
-                    (player_vec, action_vec, id_vec, use_at_vec)
-                },
-            );
-
-            let query = sqlx::query!(
-                r#"WITH data AS (SELECT *
-                                   FROM UNNEST($1::uuid[], $2::uuid[], $3::uuid[], $4::int[])
-                                     AS t(player, action, id, use_at))
-               INSERT INTO public.strategy_player_entries (player, action, id, use_at)
-                    SELECT * FROM data
-               ON CONFLICT (id)
-             DO UPDATE SET player = EXCLUDED.player,
-                           action = EXCLUDED.action,
-                           use_at = EXCLUDED.use_at"#,
-                &player_vec,
-                &action_vec,
-                &id_vec,
-                &use_at_vec
+        let changes: Vec<(Uuid, Option<EntryState>)> = accepted_deletes
+            .iter()
+            .map(|&id| (id, None))
+            .chain(
+                accepted_upserts
+                    .iter()
+                    .map(|&(player, action, id, use_at)| {
+                        (
+                            id,
+                            Some(EntryState {
+                                player,
+                                action,
+                                use_at,
+                            }),
+                        )
+                    }),
             )
-            .execute(&self.pool);
-
-            Some(query)
-        } else {
-            None
-        };
-
-        let update_modified_at_query = sqlx::query!(
-            r#"SELECT update_modified_at ($1)"#,
-            peer_context.strategy_id,
-        )
-        .execute(&self.pool);
-
-        match (delete_query, upsert_query) {
-            (Some(delete_query), Some(upsert_query)) => {
-                tokio::try_join!(delete_query, upsert_query, update_modified_at_query).unwrap();
-            }
-            (Some(delete_query), None) => {
-                tokio::try_join!(delete_query, update_modified_at_query).unwrap();
-            }
-            (None, Some(upsert_query)) => {
-                tokio::try_join!(upsert_query, update_modified_at_query).unwrap();
-            }
-            (None, None) => {}
-        }
-
-        let upserts_broadcast: Vec<Entry> = accepted_upserts
-            .into_iter()
-            .map(|(player_id, action_id, id, use_at)| Entry {
-                id: id.to_string(),
-                player: player_id.to_string(),
-                action: action_id.to_string(),
-                use_at,
-            })
-            .collect();
-
-        let deletes_broadcast: Vec<String> = accepted_deletes
-            .into_iter()
-            .map(|id| id.to_string())
             .collect();
 
-        if !upserts_broadcast.is_empty() || !deletes_broadcast.is_empty() {
-            let event = event_response::Event::MutateEntriesEvent(MutateEntriesEvent {
-                upserts: upserts_broadcast,
-                deletes: deletes_broadcast,
-            });
+        self.persist_entries(peer_context.strategy_id, strategy_context, changes.clone())
+            .await;
 
-            self.broadcast(&payload.token, &strategy_context, event)
-                .await;
-        }
+        strategy_context.queue_entry_events(&session.token, changes);
 
-        Ok(Response::new(()))
+        Ok(Response::new(MutateEntriesResponse {
+            conflicts,
+            placements,
+        }))
     }
 }
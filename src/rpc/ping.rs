@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+const PING_INTERVAL: Duration = Duration::from_millis(100);
+
+impl StratSyncService {
+    pub async fn rpc_ping(&self, request: Request<PingRequest>) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        let peer_context = self.session_peer_context(&session)?;
+        let raid = self.raid_cache.get(&peer_context.raid_id).unwrap();
+
+        if payload.at < -MAX_COUNTDOWN || payload.at > raid.duration {
+            return Err(Status::invalid_argument("At is out of range"));
+        }
+
+        let strategy = self
+            .strategies
+            .get(&peer_context.strategy_id)
+            .ok_or_else(|| Status::unauthenticated("Strategy context not opened"))?;
+
+        if let Some(player) = &payload.player {
+            if !strategy.has_player(player) {
+                return Err(Status::failed_precondition("Player not found"));
+            }
+        }
+
+        let hub = strategy.hub().clone();
+
+        let event = PingEvent {
+            peer_id: peer_context.peer_id.to_string(),
+            at: payload.at,
+            player: payload.player,
+        };
+
+        let ping_state = self.ping_states.get_with(session.token.clone(), || {
+            Arc::new(Mutex::new(PingState::default()))
+        });
+
+        {
+            let mut state = ping_state.lock().unwrap();
+            let now = Instant::now();
+
+            if let Some(last_sent_at) = state
+                .last_sent_at
+                .filter(|last_sent_at| now - *last_sent_at < PING_INTERVAL)
+            {
+                let is_flush_scheduled = state.pending.is_some();
+                state.pending = Some(event);
+
+                if !is_flush_scheduled {
+                    let delay = PING_INTERVAL - (now - last_sent_at);
+                    let ping_state = ping_state.clone();
+                    let hub = hub.clone();
+                    let token = session.token.clone();
+
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+
+                        let event = {
+                            let mut state = ping_state.lock().unwrap();
+                            state.last_sent_at = Some(Instant::now());
+                            state.pending.take()
+                        };
+
+                        if let Some(event) = event {
+                            utils::publish(
+                                &hub,
+                                Some(&token),
+                                event_response::Event::PingEvent(event),
+                            );
+                        }
+                    });
+                }
+
+                return Ok(Response::new(()));
+            }
+
+            state.last_sent_at = Some(now);
+        }
+
+        utils::publish(
+            &hub,
+            Some(&session.token),
+            event_response::Event::PingEvent(event),
+        );
+
+        Ok(Response::new(()))
+    }
+}
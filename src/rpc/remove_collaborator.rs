@@ -0,0 +1,60 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_remove_collaborator(
+        &self,
+        request: Request<RemoveCollaboratorRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can manage collaborators",
+            ));
+        }
+
+        let user_id = utils::parse_string_to_uuid(&payload.user, "User id has an invalid format")?;
+
+        let row = sqlx::query!(
+            r#"SELECT author, is_public, is_editable
+                 FROM public.strategies
+                WHERE id = $1"#,
+            peer_context.strategy_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+
+        let result = sqlx::query!(
+            r#"DELETE FROM public.strategy_collaborators
+                     WHERE strategy = $1 AND "user" = $2"#,
+            peer_context.strategy_id,
+            user_id,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+
+        if result.rows_affected() == 0 {
+            return Err(Status::failed_precondition("Collaborator not found"));
+        }
+
+        self.apply_collaborator_role(
+            strategy_context,
+            user_id,
+            None,
+            row.author,
+            row.is_public,
+            row.is_editable,
+        );
+
+        Ok(Response::new(()))
+    }
+}
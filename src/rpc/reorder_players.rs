@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_reorder_players(
+        &self,
+        request: Request<ReorderPlayersRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy_elevated!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        let ids = payload
+            .ids
+            .iter()
+            .map(|id| utils::parse_string_to_uuid(id, "id has an invalid format"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let current_ids: HashSet<String> = strategy_context
+            .players
+            .iter()
+            .map(|player| player.id.to_owned())
+            .collect();
+        let requested_ids: HashSet<String> = ids.iter().map(|id| id.to_string()).collect();
+
+        if ids.len() != current_ids.len() || requested_ids != current_ids {
+            return Err(Status::invalid_argument(
+                "ids must contain every player of the strategy exactly once",
+            ));
+        }
+
+        let mut orders: Vec<i32> = strategy_context
+            .players
+            .iter()
+            .map(|player| player.order)
+            .collect();
+        orders.sort();
+
+        tokio::try_join!(
+            sqlx::query!(
+                r#"UPDATE public.strategy_players AS p
+                      SET "order" = data."order"
+                     FROM UNNEST($1::uuid[], $2::int[]) AS data(id, "order")
+                    WHERE p.id = data.id AND p.strategy = $3"#,
+                &ids,
+                &orders,
+                peer_context.strategy_id,
+            )
+            .execute(&self.pool),
+            sqlx::query!(
+                r#"SELECT update_modified_at ($1)"#,
+                peer_context.strategy_id,
+            )
+            .execute(&self.pool),
+        )
+        .unwrap();
+
+        strategy_context.revision += 1;
+        for (id, order) in ids.iter().zip(orders) {
+            strategy_context
+                .players
+                .iter_mut()
+                .find(|player| player.id == id.to_string())
+                .unwrap()
+                .order = order;
+        }
+        strategy_context.players.sort_by_key(|player| player.order);
+
+        let players = strategy_context.players.clone();
+
+        self.broadcast(
+            &session.token,
+            strategy_context,
+            event_response::Event::PlayersReorderedEvent(PlayersReorderedEvent {
+                players,
+                entries: vec![],
+                damage_options: vec![],
+            }),
+        );
+
+        Ok(Response::new(()))
+    }
+}
@@ -0,0 +1,79 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_revoke_share_link(
+        &self,
+        request: Request<RevokeShareLinkRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can revoke share links",
+            ));
+        }
+
+        let link_id =
+            utils::parse_string_to_uuid(&payload.id, "Share link id has an invalid format")?;
+
+        let result = sqlx::query!(
+            r#"UPDATE public.strategy_share_links
+                  SET revoked = true
+                WHERE id = $1 AND strategy = $2"#,
+            link_id,
+            peer_context.strategy_id
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+
+        if result.rows_affected() == 0 {
+            return Err(Status::failed_precondition("Share link not found"));
+        }
+
+        let kicked_peers: Vec<String> = strategy_context
+            .share_link_viewers
+            .iter()
+            .filter(|(_, id)| **id == link_id)
+            .map(|(peer, _)| peer.to_owned())
+            .collect();
+
+        for peer in &kicked_peers {
+            strategy_context.share_link_viewers.remove(peer);
+            strategy_context.share_link_peers.remove(peer);
+            self.kick(peer, Some("Share link was revoked".to_string()));
+        }
+
+        let demoted_peers: Vec<String> = strategy_context
+            .share_link_peers
+            .iter()
+            .filter(|(_, id)| **id == link_id)
+            .map(|(peer, _)| peer.to_owned())
+            .collect();
+
+        if !demoted_peers.is_empty() {
+            strategy_context
+                .elevated_peers
+                .retain(|peer| !demoted_peers.contains(peer));
+            strategy_context
+                .note_peers
+                .retain(|peer| !demoted_peers.contains(peer));
+            strategy_context
+                .share_link_peers
+                .retain(|_, id| *id != link_id);
+
+            for peer in &demoted_peers {
+                self.notify_permission_changed(peer, strategy_context);
+            }
+        }
+
+        Ok(Response::new(()))
+    }
+}
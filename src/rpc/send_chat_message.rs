@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+const MAX_CHAT_MESSAGE_LENGTH: usize = 512;
+const MAX_CHAT_HISTORY: usize = 100;
+const CHAT_RATE_WINDOW: Duration = Duration::from_secs(10);
+const MAX_CHAT_MESSAGES_PER_WINDOW: u32 = 10;
+
+impl StratSyncService {
+    pub async fn rpc_send_chat_message(
+        &self,
+        request: Request<SendChatMessageRequest>,
+    ) -> Result<Response<SendChatMessageResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        let content = payload.content.trim();
+
+        if content.is_empty() {
+            return Err(Status::invalid_argument("Message is empty"));
+        }
+
+        if content.len() > MAX_CHAT_MESSAGE_LENGTH {
+            return Err(Status::invalid_argument("Message is too long"));
+        }
+
+        // New sessions must not reset the limit, so it is shared by every session
+        // of a user, or of an address for anonymous users.
+        let rate_limit_key = match (session.user_id, session.address) {
+            (Some(user_id), _) => format!("user:{}", user_id),
+            (None, Some(address)) => format!("address:{}", address),
+            (None, None) => format!("session:{}", session.token),
+        };
+
+        let now = Instant::now();
+        let rate_limit = match self.chat_rate_limits.get(&rate_limit_key) {
+            Some(rate_limit) if now - rate_limit.window_start < CHAT_RATE_WINDOW => rate_limit,
+            _ => ChatRateLimit {
+                window_start: now,
+                count: 0,
+            },
+        };
+
+        if rate_limit.count >= MAX_CHAT_MESSAGES_PER_WINDOW {
+            return Err(Status::resource_exhausted("Too many messages, slow down"));
+        }
+
+        self.chat_rate_limits.insert(
+            rate_limit_key,
+            ChatRateLimit {
+                count: rate_limit.count + 1,
+                ..rate_limit
+            },
+        );
+
+        let message = ChatMessage {
+            id: Uuid::new_v4().to_string(),
+            peer_id: peer_context.peer_id.to_string(),
+            content: content.to_string(),
+            sent_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+        };
+
+        strategy_context.chat_history.push_back(message.clone());
+        while strategy_context.chat_history.len() > MAX_CHAT_HISTORY {
+            strategy_context.chat_history.pop_front();
+        }
+
+        self.broadcast(
+            &session.token,
+            strategy_context,
+            event_response::Event::ChatMessageEvent(ChatMessageEvent {
+                message: Some(message.clone()),
+            }),
+        );
+
+        Ok(Response::new(SendChatMessageResponse {
+            message: Some(message),
+        }))
+    }
+}
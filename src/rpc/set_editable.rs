@@ -0,0 +1,40 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_set_editable(
+        &self,
+        request: Request<SetEditableRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can change whether the strategy is editable",
+            ));
+        }
+
+        sqlx::query!(
+            r#"UPDATE public.strategies
+                  SET is_editable = $1
+                WHERE id = $2"#,
+            payload.is_editable,
+            peer_context.strategy_id,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+
+        if !payload.is_editable {
+            self.demote_peers(strategy_context);
+        }
+
+        Ok(Response::new(()))
+    }
+}
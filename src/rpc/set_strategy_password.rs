@@ -0,0 +1,64 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+const MIN_PASSWORD_LENGTH: usize = 4;
+const MAX_PASSWORD_LENGTH: usize = 72;
+
+impl StratSyncService {
+    pub async fn rpc_set_strategy_password(
+        &self,
+        request: Request<SetStrategyPasswordRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        if !self.session_peer_context(&session)?.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can change the strategy password",
+            ));
+        }
+
+        let password_hash = match payload.password {
+            Some(password) => {
+                if password.len() < MIN_PASSWORD_LENGTH || password.len() > MAX_PASSWORD_LENGTH {
+                    return Err(Status::invalid_argument("Password length is out of range"));
+                }
+
+                let password_hash = tokio::task::spawn_blocking(move || {
+                    bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap()
+                })
+                .await
+                .unwrap();
+
+                Some(password_hash)
+            }
+            None => None,
+        };
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can change the strategy password",
+            ));
+        }
+
+        sqlx::query!(
+            r#"UPDATE public.strategies
+                  SET password = $1
+                WHERE id = $2"#,
+            password_hash,
+            peer_context.strategy_id,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+
+        self.demote_peers(strategy_context);
+
+        Ok(Response::new(()))
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_shift_entries(
+        &self,
+        request: Request<ShiftEntriesRequest>,
+    ) -> Result<Response<ShiftEntriesResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy_elevated!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        strategy_context.check_pending_writes()?;
+
+        let raid = self.raid_cache.get(&strategy_context.raid_id).unwrap();
+
+        if payload.from > payload.to {
+            return Err(Status::invalid_argument("from must not be greater than to"));
+        }
+
+        let player_filter = payload
+            .player
+            .as_deref()
+            .map(|id| utils::parse_string_to_uuid(id, "player has an invalid format"))
+            .transpose()?;
+        let action_filter = payload
+            .action
+            .as_deref()
+            .map(|id| utils::parse_string_to_uuid(id, "action has an invalid format"))
+            .transpose()?;
+
+        let shifted: HashMap<Uuid, i32> = strategy_context
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.use_at >= payload.from && entry.use_at <= payload.to)
+            .filter(|(_, entry)| player_filter.is_none_or(|player_id| entry.player == player_id))
+            .filter(|(_, entry)| action_filter.is_none_or(|action_id| entry.action == action_id))
+            .map(|(id, entry)| {
+                entry
+                    .use_at
+                    .checked_add(payload.delta)
+                    .map(|use_at| (id, use_at))
+                    .ok_or_else(|| Status::invalid_argument("delta is out of range"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut rejections: Vec<ShiftEntriesRejection> = Vec::new();
+
+        for (id, use_at) in &shifted {
+            if *use_at < -MAX_COUNTDOWN || *use_at > raid.duration {
+                rejections.push(ShiftEntriesRejection {
+                    id: id.to_string(),
+                    reason: "use_at is out of range".to_string(),
+                });
+            }
+        }
+
+        let mut action_lookup: HashMap<Uuid, ActionInfo> = HashMap::new();
+        for job in strategy_context
+            .players
+            .iter()
+            .filter_map(|player| player.job.as_ref())
+        {
+            for action in self.job_actions(job).iter() {
+                action_lookup.insert(action.id, action.clone());
+            }
+        }
+
+        let keys_to_check: HashSet<(Uuid, Uuid)> = shifted
+            .keys()
+            .map(|id| {
+                let entry = strategy_context.entries.get(id).unwrap();
+                (entry.player, entry.action)
+            })
+            .collect();
+
+        for (player_id, action_id) in keys_to_check {
+            let action = action_lookup
+                .get(&action_id)
+                .ok_or_else(|| Status::internal("Action not found"))?;
+
+            let column_after: Vec<(Uuid, i32)> = strategy_context
+                .entries
+                .column(player_id, action_id)
+                .map(|(id, use_at)| (id, shifted.get(&id).copied().unwrap_or(use_at)))
+                .collect();
+
+            if utils::max_simultaneous_uses(
+                column_after.iter().map(|&(_, use_at)| use_at),
+                action.cooldown,
+            ) > action.charges
+            {
+                rejections.extend(
+                    column_after
+                        .iter()
+                        .filter(|(id, _)| shifted.contains_key(id))
+                        .map(|(id, _)| ShiftEntriesRejection {
+                            id: id.to_string(),
+                            reason: "Not enough charges available".to_string(),
+                        }),
+                );
+            }
+        }
+
+        if !rejections.is_empty() {
+            return Ok(Response::new(ShiftEntriesResponse {
+                applied: false,
+                entries: vec![],
+                rejections,
+            }));
+        }
+
+        if shifted.is_empty() {
+            return Ok(Response::new(ShiftEntriesResponse {
+                applied: true,
+                entries: vec![],
+                rejections: vec![],
+            }));
+        }
+
+        let mut changes: Vec<(Uuid, Option<EntryState>)> = Vec::new();
+        let mut entries_shifted: Vec<Entry> = Vec::new();
+        for (id, use_at) in shifted {
+            let entry = EntryState {
+                use_at,
+                ..*strategy_context.entries.get(&id).unwrap()
+            };
+            strategy_context.entries.insert(id, entry);
+            changes.push((id, Some(entry)));
+            entries_shifted.push(entry.to_entry(id));
+        }
+        strategy_context.revision += 1;
+
+        self.persist_entries(peer_context.strategy_id, strategy_context, changes)
+            .await;
+
+        self.broadcast(
+            &session.token,
+            strategy_context,
+            event_response::Event::MutateEntriesEvent(MutateEntriesEvent {
+                upserts: entries_shifted.clone(),
+                deletes: vec![],
+            }),
+        );
+
+        Ok(Response::new(ShiftEntriesResponse {
+            applied: true,
+            entries: entries_shifted,
+            rejections: vec![],
+        }))
+    }
+}
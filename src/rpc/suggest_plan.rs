@@ -0,0 +1,131 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_suggest_plan(
+        &self,
+        request: Request<SuggestPlanRequest>,
+    ) -> Result<Response<SuggestPlanResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        let uses_per_damage = payload.uses_per_damage.unwrap_or(1);
+        if uses_per_damage <= 0 {
+            return Err(Status::invalid_argument(
+                "uses_per_damage must be greater than 0",
+            ));
+        }
+
+        utils::open_strategy!(
+            self,
+            &session,
+            _peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        let raid = self.raid_cache.get(&strategy_context.raid_id).unwrap();
+
+        let mut timings = Vec::new();
+        for timing in payload.damages {
+            let damage_id = Uuid::parse_str(&timing.damage)
+                .ok()
+                .filter(|damage_id| raid.damages.iter().any(|damage| damage.id == *damage_id))
+                .ok_or_else(|| {
+                    Status::failed_precondition(
+                        "Damage not found or not belongs to the specified raid",
+                    )
+                })?;
+            if timing.at < -MAX_COUNTDOWN || timing.at > raid.duration {
+                return Err(Status::invalid_argument("At is out of range"));
+            }
+            timings.push((timing.at, damage_id));
+        }
+        timings.sort_unstable();
+
+        let players: Vec<(Uuid, Arc<Vec<ActionInfo>>)> = strategy_context
+            .players
+            .iter()
+            .filter_map(|player| {
+                let job = player.job.as_ref()?;
+                Some((Uuid::parse_str(&player.id).unwrap(), self.job_actions(job)))
+            })
+            .collect();
+
+        let primary_targets: HashMap<Uuid, Uuid> = strategy_context
+            .damage_options
+            .iter()
+            .filter_map(|damage_option| {
+                Some((
+                    Uuid::parse_str(&damage_option.damage).ok()?,
+                    Uuid::parse_str(damage_option.primary_target.as_ref()?).ok()?,
+                ))
+            })
+            .collect();
+
+        // Greedy in time order: each damage takes the usable actions with the
+        // shortest cooldowns, one per player, so that longer ones stay
+        // available for later damages.
+        let mut planned: HashMap<(Uuid, Uuid), Vec<i32>> = HashMap::new();
+        let mut entries = Vec::new();
+        for (at, damage_id) in timings {
+            let primary_target = primary_targets.get(&damage_id);
+
+            let mut candidates: Vec<(i32, usize, Uuid, &ActionInfo)> = Vec::new();
+            for (order, (player_id, actions)) in players.iter().enumerate() {
+                if primary_target.is_some_and(|target| target != player_id) {
+                    continue;
+                }
+
+                for action in actions.iter() {
+                    let use_ats = strategy_context
+                        .entries
+                        .column(*player_id, action.id)
+                        .map(|(_, use_at)| use_at)
+                        .chain(
+                            planned
+                                .get(&(*player_id, action.id))
+                                .into_iter()
+                                .flatten()
+                                .copied(),
+                        );
+
+                    if !utils::available_use_ats(use_ats, action.cooldown, action.charges, at, at)
+                        .is_empty()
+                    {
+                        candidates.push((action.cooldown, order, *player_id, action));
+                    }
+                }
+            }
+            candidates.sort_by_key(|&(cooldown, order, _, _)| (cooldown, order));
+
+            let mut used_by: Vec<Uuid> = Vec::new();
+            for (_, _, player_id, action) in candidates {
+                if used_by.len() >= uses_per_damage as usize {
+                    break;
+                }
+                if used_by.contains(&player_id) {
+                    continue;
+                }
+                used_by.push(player_id);
+
+                planned.entry((player_id, action.id)).or_default().push(at);
+                entries.push(Entry {
+                    id: Uuid::new_v4().to_string(),
+                    player: player_id.to_string(),
+                    action: action.id.to_string(),
+                    use_at: at,
+                });
+            }
+        }
+
+        Ok(Response::new(SuggestPlanResponse { entries }))
+    }
+}
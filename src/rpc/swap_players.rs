@@ -0,0 +1,175 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::protos::stratsync::*;
+use crate::strategy::PendingWrites;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_swap_players(
+        &self,
+        request: Request<SwapPlayersRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy_elevated!(
+            self,
+            &session,
+            peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        strategy_context.check_pending_writes()?;
+
+        let first_id = utils::parse_string_to_uuid(&payload.first, "first has an invalid format")?;
+        let second_id =
+            utils::parse_string_to_uuid(&payload.second, "second has an invalid format")?;
+
+        if first_id == second_id {
+            return Err(Status::invalid_argument("Cannot swap a player with itself"));
+        }
+
+        let first = strategy_context
+            .players
+            .iter()
+            .find(|player| player.id == first_id.to_string())
+            .ok_or_else(|| Status::failed_precondition("Player not found"))?
+            .clone();
+        let second = strategy_context
+            .players
+            .iter()
+            .find(|player| player.id == second_id.to_string())
+            .ok_or_else(|| Status::failed_precondition("Player not found"))?
+            .clone();
+
+        strategy_context.revision += 1;
+        let mut entries_moved: Vec<Entry> = Vec::new();
+        let mut damage_options_moved: Vec<DamageOption> = Vec::new();
+
+        if payload.swap_assignments {
+            sqlx::query!(
+                r#"UPDATE public.strategy_damage_options
+                      SET primary_target = CASE WHEN primary_target = $2 THEN $3 ELSE $2 END
+                    WHERE strategy = $1
+                      AND primary_target IN ($2, $3)"#,
+                peer_context.strategy_id,
+                first_id,
+                second_id,
+            )
+            .execute(&self.pool)
+            .await
+            .unwrap();
+
+            for damage_option in strategy_context.damage_options.iter_mut() {
+                let primary_target = match damage_option
+                    .primary_target
+                    .as_deref()
+                    .and_then(|primary_target| Uuid::parse_str(primary_target).ok())
+                {
+                    Some(primary_target) if primary_target == first_id => second_id,
+                    Some(primary_target) if primary_target == second_id => first_id,
+                    _ => continue,
+                };
+
+                damage_option.primary_target = Some(primary_target.to_string());
+                damage_options_moved.push(damage_option.clone());
+            }
+
+            let (first_job, second_job) = (first.job.clone(), second.job.clone());
+            for player in strategy_context.players.iter_mut() {
+                if player.id == first.id {
+                    player.job = second_job.clone();
+                } else if player.id == second.id {
+                    player.job = first_job.clone();
+                }
+            }
+
+            let moved: Vec<(Uuid, EntryState)> = strategy_context
+                .entries
+                .ids_of_player(first_id)
+                .into_iter()
+                .chain(strategy_context.entries.ids_of_player(second_id))
+                .map(|id| (id, *strategy_context.entries.get(&id).unwrap()))
+                .collect();
+
+            let mut changes: HashMap<Uuid, Option<EntryState>> = HashMap::new();
+            for (id, entry) in moved {
+                let entry = EntryState {
+                    player: if entry.player == first_id {
+                        second_id
+                    } else {
+                        first_id
+                    },
+                    ..entry
+                };
+                strategy_context.entries.insert(id, entry);
+                changes.insert(id, Some(entry));
+                entries_moved.push(entry.to_entry(id));
+            }
+
+            let jobs = [(first_id, second_job), (second_id, first_job)]
+                .into_iter()
+                .map(|(id, job)| (id, job.map(|job| Job::from_str(&job).unwrap())))
+                .collect();
+
+            self.persist(
+                peer_context.strategy_id,
+                strategy_context,
+                PendingWrites {
+                    entries: changes,
+                    jobs,
+                },
+            )
+            .await;
+        } else {
+            tokio::try_join!(
+                sqlx::query!(
+                    r#"UPDATE public.strategy_players AS p
+                          SET "order" = o."order"
+                         FROM public.strategy_players AS o
+                        WHERE (p.id = $1 AND o.id = $2)
+                           OR (p.id = $2 AND o.id = $1)"#,
+                    first_id,
+                    second_id,
+                )
+                .execute(&self.pool),
+                sqlx::query!(
+                    r#"SELECT update_modified_at ($1)"#,
+                    peer_context.strategy_id,
+                )
+                .execute(&self.pool),
+            )
+            .unwrap();
+
+            let (first_order, second_order) = (first.order, second.order);
+            for player in strategy_context.players.iter_mut() {
+                if player.id == first.id {
+                    player.order = second_order;
+                } else if player.id == second.id {
+                    player.order = first_order;
+                }
+            }
+            strategy_context.players.sort_by_key(|player| player.order);
+        }
+
+        let players = strategy_context.players.clone();
+
+        self.broadcast(
+            &session.token,
+            strategy_context,
+            event_response::Event::PlayersReorderedEvent(PlayersReorderedEvent {
+                players,
+                entries: entries_moved,
+                damage_options: damage_options_moved,
+            }),
+        );
+
+        Ok(Response::new(()))
+    }
+}
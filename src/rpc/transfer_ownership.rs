@@ -0,0 +1,118 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_transfer_ownership(
+        &self,
+        request: Request<TransferOwnershipRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        let new_author =
+            utils::parse_string_to_uuid(&payload.user, "User id has an invalid format")?;
+
+        let row = sqlx::query!(
+            r#"SELECT author, is_public, is_editable
+                 FROM public.strategies
+                WHERE id = $1"#,
+            peer_context.strategy_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+
+        let previous_author = match session.user_id {
+            Some(user_id) if Some(user_id) == row.author => user_id,
+            _ => {
+                return Err(Status::permission_denied(
+                    "Only the author can transfer ownership",
+                ))
+            }
+        };
+
+        if new_author == previous_author {
+            return Err(Status::failed_precondition("User is already the author"));
+        }
+
+        let transfer_failed = |_| Status::internal("Failed to transfer ownership");
+        let mut transaction = self.pool.begin().await.map_err(transfer_failed)?;
+
+        sqlx::query!(
+            r#"UPDATE public.strategies
+                  SET author = $1
+                WHERE id = $2"#,
+            new_author,
+            peer_context.strategy_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(transfer_failed)?;
+
+        sqlx::query!(
+            r#"DELETE FROM public.strategy_collaborators
+                     WHERE strategy = $1 AND "user" = $2"#,
+            peer_context.strategy_id,
+            new_author,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(transfer_failed)?;
+
+        sqlx::query!(
+            r#"INSERT INTO public.strategy_collaborators (strategy, "user", role)
+                    VALUES ($1, $2, $3)
+               ON CONFLICT (strategy, "user")
+             DO UPDATE SET role = EXCLUDED.role"#,
+            peer_context.strategy_id,
+            previous_author,
+            CollaboratorRole::Editor as CollaboratorRole,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(transfer_failed)?;
+
+        sqlx::query!(
+            r#"SELECT update_modified_at ($1)"#,
+            peer_context.strategy_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(transfer_failed)?;
+
+        transaction.commit().await.map_err(transfer_failed)?;
+
+        self.apply_collaborator_role(
+            strategy_context,
+            new_author,
+            None,
+            Some(new_author),
+            row.is_public,
+            row.is_editable,
+        );
+
+        self.apply_collaborator_role(
+            strategy_context,
+            previous_author,
+            Some(CollaboratorRole::Editor),
+            Some(new_author),
+            row.is_public,
+            row.is_editable,
+        );
+
+        self.broadcast(
+            &session.token,
+            strategy_context,
+            event_response::Event::OwnershipTransferredEvent(OwnershipTransferredEvent {
+                author: new_author.to_string(),
+            }),
+        );
+
+        Ok(Response::new(()))
+    }
+}
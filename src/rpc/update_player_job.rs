@@ -1,7 +1,7 @@
-use std::str::FromStr;
-use std::sync::Arc;
+use std::{collections::HashMap, str::FromStr};
 
 use crate::protos::stratsync::*;
+use crate::strategy::PendingWrites;
 use crate::types::*;
 use crate::utils;
 
@@ -12,17 +12,20 @@ impl StratSyncService {
         &self,
         request: Request<UpdatePlayerJobRequest>,
     ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
         utils::open_strategy_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
             strategy_context
         );
 
+        strategy_context.check_pending_writes()?;
+
         let job_as_string = payload.job.clone();
 
         let id = utils::parse_string_to_uuid(&payload.id, "id has an invalid format")?;
@@ -38,51 +41,39 @@ impl StratSyncService {
             .find(|player| player.id == id.to_string())
             .ok_or_else(|| Status::failed_precondition("Player not found"))?;
 
-        tokio::try_join!(
-            sqlx::query!(
-                r#"UPDATE public.strategy_players
-                      SET job = $1
-                    WHERE id = $2"#,
-                job as Option<Job>,
-                id,
-            )
-            .execute(&self.pool),
-            sqlx::query!(
-                r#"DELETE FROM public.strategy_player_entries
-                         WHERE player = $1"#,
-                id,
-            )
-            .execute(&self.pool),
-            sqlx::query!(
-                r#"SELECT update_modified_at ($1)"#,
-                peer_context.strategy_id,
-            )
-            .execute(&self.pool),
-        )
-        .unwrap();
-
-        let mut strategy_context_after = (*strategy_context).to_owned();
-        strategy_context_after
+        strategy_context.revision += 1;
+        strategy_context
             .players
             .iter_mut()
             .find(|player| player.id == id.to_string())
             .unwrap()
             .job = payload.job.clone();
-        strategy_context_after
-            .entries
-            .retain(|entry| entry.player != id.to_string());
-        self.strategy_context
-            .insert(peer_context.strategy_id, Arc::new(strategy_context_after));
+        let removed_entries = strategy_context.entries.ids_of_player(id);
+        for entry_id in &removed_entries {
+            strategy_context.entries.remove(entry_id);
+        }
+
+        self.persist(
+            peer_context.strategy_id,
+            strategy_context,
+            PendingWrites {
+                entries: removed_entries
+                    .into_iter()
+                    .map(|entry_id| (entry_id, None))
+                    .collect(),
+                jobs: HashMap::from([(id, job)]),
+            },
+        )
+        .await;
 
         self.broadcast(
-            &payload.token,
-            &strategy_context,
+            &session.token,
+            strategy_context,
             event_response::Event::UpdatePlayerJobEvent(UpdatePlayerJobEvent {
                 id: id.to_string(),
                 job: job_as_string,
             }),
-        )
-        .await;
+        );
 
         Ok(Response::new(()))
     }
@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_upsert_collaborator(
+        &self,
+        request: Request<UpsertCollaboratorRequest>,
+    ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
+        let payload = request.into_inner();
+
+        utils::open_strategy!(self, &session, peer_context, lock, _guard, strategy_context);
+
+        if !peer_context.is_author {
+            return Err(Status::permission_denied(
+                "Only the author can manage collaborators",
+            ));
+        }
+
+        let collaborator = payload
+            .collaborator
+            .ok_or_else(|| Status::invalid_argument("No collaborator specified"))?;
+        let user_id =
+            utils::parse_string_to_uuid(&collaborator.user, "User id has an invalid format")?;
+        let role = CollaboratorRole::from_str(&collaborator.role)
+            .map_err(|_| Status::invalid_argument("Invalid role"))?;
+
+        let row = sqlx::query!(
+            r#"SELECT author, is_public, is_editable
+                 FROM public.strategies
+                WHERE id = $1"#,
+            peer_context.strategy_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+
+        if Some(user_id) == row.author {
+            return Err(Status::failed_precondition(
+                "The author cannot be added as a collaborator",
+            ));
+        }
+
+        sqlx::query!(
+            r#"INSERT INTO public.strategy_collaborators (strategy, "user", role)
+                    VALUES ($1, $2, $3)
+               ON CONFLICT (strategy, "user")
+             DO UPDATE SET role = EXCLUDED.role"#,
+            peer_context.strategy_id,
+            user_id,
+            role as CollaboratorRole,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+
+        self.apply_collaborator_role(
+            strategy_context,
+            user_id,
+            Some(role),
+            row.author,
+            row.is_public,
+            row.is_editable,
+        );
+
+        Ok(Response::new(()))
+    }
+}
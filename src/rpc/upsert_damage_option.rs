@@ -1,5 +1,3 @@
-use std::sync::Arc;
-
 use crate::protos::stratsync::*;
 use crate::types::*;
 use crate::utils;
@@ -11,11 +9,12 @@ impl StratSyncService {
         &self,
         request: Request<UpsertDamageOptionRequest>,
     ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
         utils::open_strategy_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
@@ -37,39 +36,7 @@ impl StratSyncService {
 
         let num_shared = damage_option.num_shared;
 
-        let damage = raid
-            .damages
-            .iter()
-            .find(|damage| damage.id == damage_id)
-            .ok_or_else(|| {
-                Status::failed_precondition("Damage not found or not belongs to the specified raid")
-            })?;
-
-        if let Some(s) = num_shared {
-            if s > damage.max_shared {
-                return Err(Status::failed_precondition(
-                    "num_shared is greater than max_shared",
-                ));
-            }
-        }
-
-        if let Some(s) = primary_target_id {
-            if !strategy_context
-                .players
-                .iter()
-                .any(|player| player.id == s.to_string())
-            {
-                return Err(Status::failed_precondition("Primary target not found"));
-            }
-        }
-
-        let damage_options_after: Vec<_> = strategy_context
-            .damage_options
-            .iter()
-            .filter(|damage_option| damage_option.damage != damage_id.to_string())
-            .chain([damage_option.clone()].iter())
-            .map(|damage_option| damage_option.to_owned())
-            .collect();
+        utils::validate_damage_option(&damage_option, &raid, &strategy_context.players)?;
 
         tokio::try_join!(
             sqlx::query!(
@@ -92,19 +59,19 @@ impl StratSyncService {
         )
         .unwrap();
 
-        let mut strategy_context_after = (*strategy_context).to_owned();
-        strategy_context_after.damage_options = damage_options_after;
-        self.strategy_context
-            .insert(peer_context.strategy_id, Arc::new(strategy_context_after));
+        strategy_context.revision += 1;
+        strategy_context
+            .damage_options
+            .retain(|damage_option| damage_option.damage != damage_id.to_string());
+        strategy_context.damage_options.push(damage_option.clone());
 
         self.broadcast(
-            &payload.token,
-            &strategy_context,
+            &session.token,
+            strategy_context,
             event_response::Event::UpsertDamageOptionEvent(UpsertDamageOptionEvent {
                 damage_option: Some(damage_option),
             }),
-        )
-        .await;
+        );
 
         Ok(Response::new(()))
     }
@@ -4,18 +4,17 @@ use crate::utils;
 
 use tonic::{Request, Response, Status};
 
-const MAX_NOTE_LENGTH: usize = 128;
-
 impl StratSyncService {
     pub async fn rpc_upsert_note(
         &self,
         request: Request<UpsertNoteRequest>,
     ) -> Result<Response<()>, Status> {
+        let session = utils::parse_session(&request).await?;
         let payload = request.into_inner();
 
-        utils::open_strategy_elevated!(
+        utils::open_strategy_notes_elevated!(
             self,
-            &payload.token,
+            &session,
             peer_context,
             lock,
             _guard,
@@ -30,21 +29,7 @@ impl StratSyncService {
 
         let note_id = utils::parse_string_to_uuid(&note.id, "Note id has an invalid format")?;
 
-        if note.block < 1 || note.block > raid.headcount + 1 {
-            return Err(Status::invalid_argument("Block is out of range"));
-        }
-
-        if note.offset < 0f32 || note.offset > 1f32 {
-            return Err(Status::invalid_argument("Offset is out of range"));
-        }
-
-        if note.at < -MAX_COUNTDOWN || note.at > raid.duration {
-            return Err(Status::invalid_argument("At is out of range"));
-        }
-
-        if note.content.len() > MAX_NOTE_LENGTH {
-            return Err(Status::invalid_argument("Note text is too long"));
-        }
+        utils::validate_note(&note, &raid)?;
 
         tokio::try_join!(
             sqlx::query!(
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use sqlx::types::Uuid;
+use tonic::{Request, Response, Status};
+
+impl StratSyncService {
+    pub async fn rpc_validate_strategy(
+        &self,
+        request: Request<ValidateStrategyRequest>,
+    ) -> Result<Response<ValidateStrategyResponse>, Status> {
+        let session = utils::parse_session(&request).await?;
+
+        let peer_context = self.session_peer_context(&session)?;
+        self.reload_catalog(peer_context.raid_id).await?;
+        let notes = self.fetch_notes(peer_context.strategy_id).await?;
+
+        utils::open_strategy!(
+            self,
+            &session,
+            _peer_context,
+            lock,
+            _guard,
+            strategy_context
+        );
+
+        let violations = self.validate_strategy(strategy_context, &notes);
+        strategy_context.violations = violations.clone();
+
+        Ok(Response::new(ValidateStrategyResponse { violations }))
+    }
+
+    pub async fn fetch_notes(&self, strategy_id: Uuid) -> Result<Vec<Note>, Status> {
+        sqlx::query_as!(
+            Note,
+            r#"SELECT id::text AS "id!", block, "offset", at, content
+                 FROM public.notes
+                WHERE strategy = $1"#,
+            strategy_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| Status::internal("Failed to load notes"))
+    }
+
+    // Data written before a catalog change, or edited in the database, may no
+    // longer pass the checks done on edit.
+    pub fn validate_strategy(
+        &self,
+        strategy_context: &StrategyContext,
+        notes: &[Note],
+    ) -> Vec<Violation> {
+        let raid = self.raid_cache.get(&strategy_context.raid_id).unwrap();
+        let mut violations = Vec::new();
+
+        let player_lookup: HashMap<Uuid, &Player> = strategy_context
+            .players
+            .iter()
+            .map(|player| (Uuid::parse_str(&player.id).unwrap(), player))
+            .collect();
+
+        let mut columns: HashMap<(Uuid, Uuid), Vec<(Uuid, i32)>> = HashMap::new();
+        let mut actions: HashMap<Uuid, ActionInfo> = HashMap::new();
+        for (id, entry) in strategy_context.entries.iter() {
+            let violation = |rule: &str, message: &str| Violation {
+                rule: rule.to_string(),
+                message: message.to_string(),
+                entries: vec![id.to_string()],
+                player: Some(entry.player.to_string()),
+                ..Default::default()
+            };
+
+            if entry.use_at < -MAX_COUNTDOWN || entry.use_at > raid.duration {
+                violations.push(violation("use_at", "use_at is out of range"));
+            }
+
+            let job = match player_lookup.get(&entry.player) {
+                Some(player) => player.job.as_ref(),
+                None => {
+                    violations.push(violation("player", "Player not found"));
+                    continue;
+                }
+            };
+            let action = job.and_then(|job| {
+                self.job_actions(job)
+                    .iter()
+                    .find(|action| action.id == entry.action)
+                    .cloned()
+            });
+
+            match (job, action) {
+                (None, _) => violations.push(violation("job", "Player has an empty job")),
+                (Some(_), None) => violations.push(violation(
+                    "action",
+                    "Action does not belong to the job of the player",
+                )),
+                (Some(_), Some(action)) => {
+                    actions.insert(action.id, action);
+                    columns
+                        .entry((entry.player, entry.action))
+                        .or_default()
+                        .push((id, entry.use_at));
+                }
+            }
+        }
+
+        for ((player_id, action_id), column) in columns {
+            let action = &actions[&action_id];
+            if utils::max_simultaneous_uses(
+                column.iter().map(|&(_, use_at)| use_at),
+                action.cooldown,
+            ) > action.charges
+            {
+                violations.push(Violation {
+                    rule: "cooldown".to_string(),
+                    message: "Action is used more often than its charges allow".to_string(),
+                    entries: column.iter().map(|(id, _)| id.to_string()).collect(),
+                    player: Some(player_id.to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        for damage_option in &strategy_context.damage_options {
+            if let Err(status) =
+                utils::validate_damage_option(damage_option, &raid, &strategy_context.players)
+            {
+                violations.push(Violation {
+                    rule: "damage_option".to_string(),
+                    message: status.message().to_string(),
+                    damage: Some(damage_option.damage.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        for note in notes {
+            if let Err(status) = utils::validate_note(note, &raid) {
+                violations.push(Violation {
+                    rule: "note".to_string(),
+                    message: status.message().to_string(),
+                    note: Some(note.id.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        violations
+    }
+}
@@ -1,18 +1,23 @@
 use crate::protos::stratsync::*;
+use crate::strategy::StrategyHandle;
 use crate::types::*;
+use crate::utils;
 
-use moka::sync::Cache;
+use moka::{notification::RemovalCause, sync::Cache};
 use sqlx::{postgres::PgPoolOptions, types::Uuid};
-use std::{env, sync::Arc, time::Duration};
+use std::{env, net::IpAddr, sync::Arc, time::Duration};
 use strat_sync_server::{StratSync, StratSyncServer};
-use tokio::sync::Mutex;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
-const STRATEGY_CAPACITY: u64 = 65536;
-const STRATEGY_TTI: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
 const PEER_CAPACITY: u64 = 65536;
 const PEER_TTI: Duration = Duration::from_secs(12 * 60 * 60); // 12 hours
+const ELEVATION_ATTEMPTS_CAPACITY: u64 = 65536;
+const ELEVATION_ATTEMPTS_TTL: Duration = Duration::from_secs(60 * 60); // 1 hour
+const ELEVATION_BANS_CAPACITY: u64 = 65536;
+const ELEVATION_BANS_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+const CHAT_RATE_LIMITS_TTI: Duration = Duration::from_secs(60); // 1 minute
+const PING_STATES_TTI: Duration = Duration::from_secs(60); // 1 minute
 
 #[tonic::async_trait]
 impl StratSync for StratSyncService {
@@ -29,46 +34,200 @@ impl StratSync for StratSyncService {
         &self,
         request: Request<ClearOtherSessionsRequest>,
     ) -> Result<Response<()>, Status> {
-        self.rpc_clear_other_sessions(request).await
+        self.idempotent(request, |request| self.rpc_clear_other_sessions(request))
+            .await
     }
 
     async fn elevate(&self, request: Request<ElevationRequest>) -> Result<Response<()>, Status> {
-        self.rpc_elevate(request).await
+        self.idempotent(request, |request| self.rpc_elevate(request))
+            .await
+    }
+
+    async fn kick_peer(&self, request: Request<KickPeerRequest>) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_kick_peer(request))
+            .await
+    }
+
+    async fn list_peers(
+        &self,
+        request: Request<ListPeersRequest>,
+    ) -> Result<Response<ListPeersResponse>, Status> {
+        self.rpc_list_peers(request).await
+    }
+
+    async fn set_strategy_password(
+        &self,
+        request: Request<SetStrategyPasswordRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_set_strategy_password(request))
+            .await
+    }
+
+    async fn set_editable(
+        &self,
+        request: Request<SetEditableRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_set_editable(request))
+            .await
+    }
+
+    async fn create_share_link(
+        &self,
+        request: Request<CreateShareLinkRequest>,
+    ) -> Result<Response<CreateShareLinkResponse>, Status> {
+        self.idempotent(request, |request| self.rpc_create_share_link(request))
+            .await
+    }
+
+    async fn list_share_links(
+        &self,
+        request: Request<ListShareLinksRequest>,
+    ) -> Result<Response<ListShareLinksResponse>, Status> {
+        self.rpc_list_share_links(request).await
+    }
+
+    async fn revoke_share_link(
+        &self,
+        request: Request<RevokeShareLinkRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_revoke_share_link(request))
+            .await
+    }
+
+    async fn transfer_ownership(
+        &self,
+        request: Request<TransferOwnershipRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_transfer_ownership(request))
+            .await
+    }
+
+    async fn upsert_collaborator(
+        &self,
+        request: Request<UpsertCollaboratorRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_upsert_collaborator(request))
+            .await
+    }
+
+    async fn remove_collaborator(
+        &self,
+        request: Request<RemoveCollaboratorRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_remove_collaborator(request))
+            .await
+    }
+
+    async fn list_collaborators(
+        &self,
+        request: Request<ListCollaboratorsRequest>,
+    ) -> Result<Response<ListCollaboratorsResponse>, Status> {
+        self.rpc_list_collaborators(request).await
     }
 
     async fn upsert_damage_option(
         &self,
         request: Request<UpsertDamageOptionRequest>,
     ) -> Result<Response<()>, Status> {
-        self.rpc_upsert_damage_option(request).await
+        self.idempotent(request, |request| self.rpc_upsert_damage_option(request))
+            .await
     }
 
     async fn mutate_entries(
         &self,
         request: Request<MutateEntriesRequest>,
-    ) -> Result<Response<()>, Status> {
-        self.rpc_mutate_entries(request).await
+    ) -> Result<Response<MutateEntriesResponse>, Status> {
+        self.idempotent(request, |request| self.rpc_mutate_entries(request))
+            .await
+    }
+
+    async fn shift_entries(
+        &self,
+        request: Request<ShiftEntriesRequest>,
+    ) -> Result<Response<ShiftEntriesResponse>, Status> {
+        self.idempotent(request, |request| self.rpc_shift_entries(request))
+            .await
+    }
+
+    async fn copy_entries(
+        &self,
+        request: Request<CopyEntriesRequest>,
+    ) -> Result<Response<CopyEntriesResponse>, Status> {
+        self.idempotent(request, |request| self.rpc_copy_entries(request))
+            .await
     }
 
     async fn update_player_job(
         &self,
         request: Request<UpdatePlayerJobRequest>,
     ) -> Result<Response<()>, Status> {
-        self.rpc_update_player_job(request).await
+        self.idempotent(request, |request| self.rpc_update_player_job(request))
+            .await
+    }
+
+    async fn reorder_players(
+        &self,
+        request: Request<ReorderPlayersRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_reorder_players(request))
+            .await
+    }
+
+    async fn swap_players(
+        &self,
+        request: Request<SwapPlayersRequest>,
+    ) -> Result<Response<()>, Status> {
+        self.idempotent(request, |request| self.rpc_swap_players(request))
+            .await
     }
 
     async fn upsert_note(
         &self,
         request: Request<UpsertNoteRequest>,
     ) -> Result<Response<()>, Status> {
-        self.rpc_upsert_note(request).await
+        self.idempotent(request, |request| self.rpc_upsert_note(request))
+            .await
+    }
+
+    async fn send_chat_message(
+        &self,
+        request: Request<SendChatMessageRequest>,
+    ) -> Result<Response<SendChatMessageResponse>, Status> {
+        self.idempotent(request, |request| self.rpc_send_chat_message(request))
+            .await
+    }
+
+    async fn ping(&self, request: Request<PingRequest>) -> Result<Response<()>, Status> {
+        self.rpc_ping(request).await
     }
 
     async fn delete_note(
         &self,
         request: Request<DeleteNoteRequest>,
     ) -> Result<Response<()>, Status> {
-        self.rpc_delete_note(request).await
+        self.idempotent(request, |request| self.rpc_delete_note(request))
+            .await
+    }
+
+    async fn get_availability(
+        &self,
+        request: Request<GetAvailabilityRequest>,
+    ) -> Result<Response<GetAvailabilityResponse>, Status> {
+        self.rpc_get_availability(request).await
+    }
+
+    async fn validate_strategy(
+        &self,
+        request: Request<ValidateStrategyRequest>,
+    ) -> Result<Response<ValidateStrategyResponse>, Status> {
+        self.rpc_validate_strategy(request).await
+    }
+
+    async fn suggest_plan(
+        &self,
+        request: Request<SuggestPlanRequest>,
+    ) -> Result<Response<SuggestPlanResponse>, Status> {
+        self.rpc_suggest_plan(request).await
     }
 }
 
@@ -82,42 +241,49 @@ pub async fn build_stratsync() -> StratSyncServer<StratSyncService> {
         .await
         .expect("Unable to connect to database");
 
+    utils::load_keys();
+
+    let write_behind = env::var("WRITE_BEHIND").is_ok_and(|value| value == "true");
+    let bind_session_address = env::var("BIND_SESSION_ADDRESS").is_ok_and(|value| value == "true");
+
     let action_cache: Cache<String, Arc<Vec<ActionInfo>>> = Cache::builder().build();
 
-    sqlx::query!(
+    let actions = sqlx::query!(
         r#"SELECT id, job AS "job: String", cooldown, charges
            FROM public.actions"#
     )
     .fetch_all(&pool)
     .await
-    .unwrap()
-    .iter()
-    .for_each(|row| {
-        let mut abilities = (*action_cache.get(&row.job).unwrap_or(Arc::new(vec![]))).to_owned();
-        abilities.push(ActionInfo {
-            id: row.id,
-            cooldown: row.cooldown,
-            charges: row.charges,
-        });
-
-        action_cache.insert(row.job.to_owned(), Arc::new(abilities))
-    });
+    .unwrap();
+
+    utils::cache_actions(
+        &action_cache,
+        actions.into_iter().map(|row| {
+            (
+                row.job,
+                ActionInfo {
+                    id: row.id,
+                    cooldown: row.cooldown,
+                    charges: row.charges,
+                },
+            )
+        }),
+    );
 
     let raid_cache: Cache<Uuid, Arc<RaidInfo>> = Cache::builder().build();
 
-    let strategy_lock: Cache<Uuid, Arc<Mutex<()>>> = Cache::builder().build();
-    let strategy_context: Cache<Uuid, Arc<StrategyContext>> = Cache::builder()
-        .max_capacity(STRATEGY_CAPACITY)
-        .time_to_idle(STRATEGY_TTI)
-        .build();
+    let strategies: Cache<Uuid, StrategyHandle> = Cache::builder().build();
 
-    let strategy_lock_cloned = strategy_lock.clone();
-    let strategy_context_cloned = strategy_context.clone();
+    let strategies_cloned = strategies.clone();
     let peer_context: Cache<String, Arc<PeerContext>> = Cache::builder()
         .max_capacity(PEER_CAPACITY)
         .time_to_idle(PEER_TTI)
-        .eviction_listener(move |k: Arc<String>, v: Arc<PeerContext>, _| {
-            if !v.tx.is_closed() {
+        .eviction_listener(move |k: Arc<String>, v: Arc<PeerContext>, cause| {
+            if cause == RemovalCause::Replaced {
+                return;
+            }
+
+            if cause != RemovalCause::Explicit && !v.tx.is_closed() {
                 let cloned_tx = v.tx.clone();
                 tokio::spawn(async move {
                     cloned_tx
@@ -127,39 +293,48 @@ pub async fn build_stratsync() -> StratSyncServer<StratSyncService> {
                 });
             }
 
-            let mut context = (*strategy_context_cloned.get(&v.strategy_id).unwrap()).clone();
-
-            let peers_after: Vec<_> = context
-                .peers
-                .iter()
-                .filter(|&peer_id| *peer_id != *k)
-                .map(|peer_id| peer_id.to_owned())
-                .collect();
-
-            let elevated_peers_after: Vec<_> = context
-                .elevated_peers
-                .iter()
-                .filter(|&peer_id| *peer_id != *k)
-                .map(|peer_id| peer_id.to_owned())
-                .collect();
-
-            if peers_after.is_empty() {
-                strategy_lock_cloned.invalidate(&v.strategy_id);
-                strategy_context_cloned.invalidate(&v.strategy_id);
-            } else {
-                context.peers = peers_after;
-                context.elevated_peers = elevated_peers_after;
-                strategy_context_cloned.insert(v.strategy_id, Arc::new(context));
+            if let Some(strategy) = strategies_cloned.get(&v.strategy_id) {
+                strategy.leave(k.to_string());
             }
         })
         .build();
 
+    let strategy_elevation_attempts: Cache<Uuid, ElevationAttempts> = Cache::builder()
+        .max_capacity(ELEVATION_ATTEMPTS_CAPACITY)
+        .time_to_live(ELEVATION_ATTEMPTS_TTL)
+        .build();
+    let address_elevation_attempts: Cache<IpAddr, ElevationAttempts> = Cache::builder()
+        .max_capacity(ELEVATION_ATTEMPTS_CAPACITY)
+        .time_to_live(ELEVATION_ATTEMPTS_TTL)
+        .build();
+
+    let elevation_bans: Cache<Uuid, ElevationBans> = Cache::builder()
+        .max_capacity(ELEVATION_BANS_CAPACITY)
+        .time_to_live(ELEVATION_BANS_TTL)
+        .build();
+
+    let chat_rate_limits: Cache<String, ChatRateLimit> = Cache::builder()
+        .max_capacity(PEER_CAPACITY)
+        .time_to_idle(CHAT_RATE_LIMITS_TTI)
+        .build();
+
+    let ping_states: Cache<String, Arc<std::sync::Mutex<PingState>>> = Cache::builder()
+        .max_capacity(PEER_CAPACITY)
+        .time_to_idle(PING_STATES_TTI)
+        .build();
+
     StratSyncServer::new(StratSyncService {
         pool,
+        write_behind,
+        bind_session_address,
         action_cache,
         raid_cache,
-        strategy_lock,
-        strategy_context,
+        strategies,
         peer_context,
+        strategy_elevation_attempts,
+        address_elevation_attempts,
+        elevation_bans,
+        chat_rate_limits,
+        ping_states,
     })
 }
@@ -0,0 +1,556 @@
+use crate::protos::stratsync::*;
+use crate::types::*;
+use crate::utils;
+
+use moka::sync::Cache;
+use sqlx::{types::Uuid, Pool, Postgres};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, Notify},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tonic::Status;
+
+const STRATEGY_TTI: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const FLUSH_DELAY: Duration = Duration::from_millis(250);
+const FLUSH_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_FLUSH_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_FLUSH_FAILURES: u32 = 5;
+const MAX_PENDING_WRITES: usize = 10_000;
+const ENTRY_EVENTS_WINDOW: Duration = Duration::from_millis(50);
+
+enum StrategyCommand {
+    Acquire(oneshot::Sender<StrategyLease>),
+    Leave(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StrategyHandle {
+    commands: mpsc::UnboundedSender<StrategyCommand>,
+    hub: broadcast::Sender<Arc<HubEvent>>,
+    player_ids: Arc<HashSet<String>>,
+}
+
+// The state goes back to the strategy task when the lease is dropped.
+pub struct StrategyLease {
+    state: Option<Box<StrategyContext>>,
+    release: Option<oneshot::Sender<Box<StrategyContext>>>,
+}
+
+// Changes applied in memory but not written yet. A newer change to the same
+// entry or job replaces the pending one.
+#[derive(Debug, Default)]
+pub struct WriteBehind {
+    pending: Mutex<PendingWrites>,
+    writing: tokio::sync::Mutex<()>,
+    failures: AtomicU32,
+    notify: Notify,
+}
+
+#[derive(Debug, Default)]
+pub struct PendingWrites {
+    pub entries: HashMap<Uuid, Option<EntryState>>,
+    pub jobs: HashMap<Uuid, Option<Job>>,
+}
+
+impl PendingWrites {
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.jobs.is_empty()
+    }
+}
+
+impl WriteBehind {
+    pub fn push(&self, writes: PendingWrites) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.entries.extend(writes.entries);
+        pending.jobs.extend(writes.jobs);
+        self.notify.notify_one();
+    }
+
+    // Changes are rejected rather than queued without bound while the database
+    // is failing or can't keep up.
+    pub fn check(&self) -> Result<(), Status> {
+        if self.failures.load(Ordering::Relaxed) >= MAX_FLUSH_FAILURES
+            || self.pending.lock().unwrap().len() >= MAX_PENDING_WRITES
+        {
+            return Err(Status::unavailable("Changes cannot be saved right now"));
+        }
+
+        Ok(())
+    }
+
+    fn take(&self) -> PendingWrites {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    fn restore(&self, batch: PendingWrites) {
+        let mut pending = self.pending.lock().unwrap();
+        for (id, change) in batch.entries {
+            pending.entries.entry(id).or_insert(change);
+        }
+        for (id, job) in batch.jobs {
+            pending.jobs.entry(id).or_insert(job);
+        }
+    }
+
+    // Writes are serialized so that an older batch can never land after a newer
+    // one.
+    async fn write_pending(
+        &self,
+        pool: &Pool<Postgres>,
+        strategy_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let _writing = self.writing.lock().await;
+
+        let batch = self.take();
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        match write_changes(pool, strategy_id, &batch).await {
+            Ok(()) => {
+                self.failures.store(0, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(error) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                tracing::error!(
+                    strategy = %strategy_id,
+                    pending = batch.len(),
+                    %error,
+                    "Failed to write behind changes"
+                );
+
+                self.restore(batch);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl StrategyContext {
+    pub fn check_pending_writes(&self) -> Result<(), Status> {
+        match &self.write_behind {
+            Some(write_behind) => write_behind.check(),
+            None => Ok(()),
+        }
+    }
+
+    // Changes to the same entry within a short window are sent as one event.
+    pub fn queue_entry_events(
+        &mut self,
+        origin: &String,
+        changes: Vec<(Uuid, Option<EntryState>)>,
+    ) {
+        if changes.is_empty() {
+            return;
+        }
+
+        if self.entry_events.origin.as_ref() != Some(origin) {
+            self.flush_entry_events();
+            self.entry_events.origin = Some(origin.to_owned());
+        }
+
+        self.entry_events.changes.extend(changes);
+        if self.entry_events.deadline.is_none() {
+            self.entry_events.deadline = Some(Instant::now() + ENTRY_EVENTS_WINDOW);
+        }
+    }
+
+    pub fn flush_entry_events(&mut self) {
+        let pending = std::mem::take(&mut self.entry_events);
+        if pending.changes.is_empty() {
+            return;
+        }
+
+        let (mut upserts, mut deletes) = (Vec::new(), Vec::new());
+        for (id, change) in pending.changes {
+            match change {
+                Some(entry) => upserts.push(entry.to_entry(id)),
+                None => deletes.push(id.to_string()),
+            }
+        }
+
+        utils::publish(
+            &self.hub,
+            pending.origin.as_ref(),
+            event_response::Event::MutateEntriesEvent(MutateEntriesEvent { upserts, deletes }),
+        );
+    }
+}
+
+impl StrategyHandle {
+    pub fn spawn(
+        strategy_id: Uuid,
+        state: StrategyContext,
+        pool: Pool<Postgres>,
+        strategies: Cache<Uuid, StrategyHandle>,
+        peer_context: Cache<String, Arc<PeerContext>>,
+    ) -> Self {
+        let flusher = state.write_behind.clone().map(|write_behind| {
+            let (close, closed) = oneshot::channel();
+            let task = tokio::spawn(flush(strategy_id, pool.clone(), write_behind, closed));
+            (close, task)
+        });
+
+        let hub = state.hub.clone();
+        let player_ids = Arc::new(
+            state
+                .players
+                .iter()
+                .map(|player| player.id.clone())
+                .collect(),
+        );
+
+        let (commands, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(
+            strategy_id,
+            state,
+            rx,
+            pool,
+            flusher,
+            strategies,
+            peer_context,
+        ));
+
+        StrategyHandle {
+            commands,
+            hub,
+            player_ids,
+        }
+    }
+
+    pub async fn acquire(&self) -> Option<StrategyLease> {
+        let (reply, lease) = oneshot::channel();
+        self.commands.send(StrategyCommand::Acquire(reply)).ok()?;
+
+        lease.await.ok()
+    }
+
+    pub fn leave(&self, token: String) {
+        self.commands.send(StrategyCommand::Leave(token)).ok();
+    }
+
+    // Players are never added or removed while a strategy is open, so these
+    // don't have to wait for a lease.
+    pub fn hub(&self) -> &broadcast::Sender<Arc<HubEvent>> {
+        &self.hub
+    }
+
+    pub fn has_player(&self, player_id: &str) -> bool {
+        self.player_ids.contains(player_id)
+    }
+}
+
+impl Deref for StrategyLease {
+    type Target = StrategyContext;
+
+    fn deref(&self) -> &StrategyContext {
+        self.state.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for StrategyLease {
+    fn deref_mut(&mut self) -> &mut StrategyContext {
+        self.state.as_mut().unwrap()
+    }
+}
+
+impl Drop for StrategyLease {
+    fn drop(&mut self) {
+        if let (Some(state), Some(release)) = (self.state.take(), self.release.take()) {
+            release.send(state).ok();
+        }
+    }
+}
+
+async fn run(
+    strategy_id: Uuid,
+    state: StrategyContext,
+    mut commands: mpsc::UnboundedReceiver<StrategyCommand>,
+    pool: Pool<Postgres>,
+    flusher: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    strategies: Cache<Uuid, StrategyHandle>,
+    peer_context: Cache<String, Arc<PeerContext>>,
+) {
+    let hub = state.hub.clone();
+    let write_behind = state.write_behind.clone();
+    let mut state = Box::new(state);
+    let mut heartbeat = time::interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    heartbeat.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let idle = time::sleep(STRATEGY_TTI);
+    tokio::pin!(idle);
+
+    // Revision of the last state that had no entry events waiting, so that a
+    // heartbeat sent while the state is leased never runs ahead of the events.
+    let mut published_revision = state.revision;
+
+    let peers = loop {
+        tokio::select! {
+            command = commands.recv() => {
+                let command = match command {
+                    Some(command) => command,
+                    None => break Some(state.peers),
+                };
+                idle.as_mut().reset(Instant::now() + STRATEGY_TTI);
+
+                match command {
+                    StrategyCommand::Acquire(reply) => {
+                        let (release, mut released) = oneshot::channel();
+                        if state.entry_events.changes.is_empty() {
+                            published_revision = state.revision;
+                        }
+
+                        // If the requester has gone away, the lease is dropped here and
+                        // hands the state straight back.
+                        reply
+                            .send(StrategyLease {
+                                state: Some(state),
+                                release: Some(release),
+                            })
+                            .ok();
+
+                        let released = loop {
+                            tokio::select! {
+                                released = &mut released => break released,
+                                _ = heartbeat.tick() => publish_heartbeat(&hub, published_revision),
+                            }
+                        };
+
+                        state = match released {
+                            Ok(state) => state,
+                            Err(_) => {
+                                tracing::error!(
+                                    strategy = %strategy_id,
+                                    "Strategy state was not released, closing"
+                                );
+                                break None;
+                            }
+                        };
+                    }
+                    StrategyCommand::Leave(token) => {
+                        state.peers.retain(|peer| *peer != token);
+                        state.elevated_peers.retain(|peer| *peer != token);
+                        state.note_peers.retain(|peer| *peer != token);
+                        state.share_link_peers.remove(&token);
+                        state.share_link_viewers.remove(&token);
+                        state.idempotency_keys.remove(&token);
+                    }
+                }
+
+                if state.peers.is_empty() {
+                    if write_pending(&pool, strategy_id, &write_behind).await {
+                        break Some(state.peers);
+                    }
+                    idle.as_mut().reset(Instant::now() + FLUSH_RETRY_DELAY);
+                }
+            }
+            _ = heartbeat.tick() => {
+                for peer in &state.peers {
+                    if peer_context
+                        .get(peer)
+                        .is_none_or(|peer_context| peer_context.tx.is_closed())
+                    {
+                        peer_context.invalidate(peer);
+                    }
+                }
+
+                if !state.peers.is_empty() {
+                    idle.as_mut().reset(Instant::now() + STRATEGY_TTI);
+                }
+
+                // Peers must have every entry change up to the revision they are told
+                // about.
+                state.flush_entry_events();
+                publish_heartbeat(&hub, state.revision);
+            }
+            _ = time::sleep_until(state.entry_events.deadline.unwrap_or_else(Instant::now)),
+                if state.entry_events.deadline.is_some() =>
+            {
+                state.flush_entry_events();
+            }
+            _ = &mut idle => {
+                if write_pending(&pool, strategy_id, &write_behind).await {
+                    break Some(state.peers);
+                }
+                idle.as_mut().reset(Instant::now() + FLUSH_RETRY_DELAY);
+            }
+        }
+    };
+
+    // Pending writes have to land before the strategy can be reopened from the
+    // database. Until then it stays open and keeps serving from memory, except
+    // when its state was lost.
+    let mut retry_delay = FLUSH_RETRY_DELAY;
+    while !write_pending(&pool, strategy_id, &write_behind).await {
+        time::sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(MAX_FLUSH_RETRY_DELAY);
+    }
+
+    if let Some((close, task)) = flusher {
+        close.send(()).ok();
+        task.await.ok();
+    }
+
+    strategies.invalidate(&strategy_id);
+
+    match peers {
+        Some(peers) => {
+            for peer in &peers {
+                peer_context.invalidate(peer);
+            }
+        }
+        // The peer list went with the state, so look the peers up instead.
+        None => {
+            for (peer, _) in peer_context
+                .iter()
+                .filter(|(_, peer_context)| peer_context.strategy_id == strategy_id)
+            {
+                peer_context.invalidate(peer.as_ref());
+            }
+        }
+    }
+}
+
+fn publish_heartbeat(hub: &broadcast::Sender<Arc<HubEvent>>, revision: i64) {
+    let server_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+
+    utils::publish(
+        hub,
+        None,
+        event_response::Event::HeartbeatEvent(HeartbeatEvent {
+            server_time,
+            revision,
+        }),
+    );
+}
+
+async fn write_pending(
+    pool: &Pool<Postgres>,
+    strategy_id: Uuid,
+    write_behind: &Option<Arc<WriteBehind>>,
+) -> bool {
+    match write_behind {
+        Some(write_behind) => write_behind.write_pending(pool, strategy_id).await.is_ok(),
+        None => true,
+    }
+}
+
+async fn flush(
+    strategy_id: Uuid,
+    pool: Pool<Postgres>,
+    write_behind: Arc<WriteBehind>,
+    mut closed: oneshot::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            _ = write_behind.notify.notified() => {}
+            _ = &mut closed => return,
+        }
+
+        let mut delay = FLUSH_DELAY;
+        let mut retry_delay = FLUSH_RETRY_DELAY;
+        loop {
+            tokio::select! {
+                _ = time::sleep(delay) => {}
+                _ = &mut closed => return,
+            }
+
+            if write_behind.write_pending(&pool, strategy_id).await.is_ok() {
+                break;
+            }
+
+            delay = retry_delay;
+            retry_delay = (retry_delay * 2).min(MAX_FLUSH_RETRY_DELAY);
+        }
+    }
+}
+
+pub async fn write_changes(
+    pool: &Pool<Postgres>,
+    strategy_id: Uuid,
+    changes: &PendingWrites,
+) -> Result<(), sqlx::Error> {
+    let mut delete_vec = Vec::new();
+    let (mut player_vec, mut action_vec, mut id_vec, mut use_at_vec) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+
+    for (&id, change) in &changes.entries {
+        match change {
+            Some(entry) => {
+                player_vec.push(entry.player);
+                action_vec.push(entry.action);
+                id_vec.push(id);
+                use_at_vec.push(entry.use_at);
+            }
+            None => delete_vec.push(id),
+        }
+    }
+
+    let mut transaction = pool.begin().await?;
+
+    for (&id, job) in &changes.jobs {
+        sqlx::query!(
+            r#"UPDATE public.strategy_players
+                  SET job = $1
+                WHERE id = $2"#,
+            job.clone() as Option<Job>,
+            id,
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    if !delete_vec.is_empty() {
+        sqlx::query!(
+            r#"DELETE FROM public.strategy_player_entries
+                     WHERE id = ANY($1)"#,
+            &delete_vec
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    if !id_vec.is_empty() {
+        sqlx::query!(
+            r#"WITH data AS (SELECT *
+                               FROM UNNEST($1::uuid[], $2::uuid[], $3::uuid[], $4::int[])
+                                 AS t(player, action, id, use_at))
+           INSERT INTO public.strategy_player_entries (player, action, id, use_at)
+                SELECT * FROM data
+           ON CONFLICT (id)
+         DO UPDATE SET player = EXCLUDED.player,
+                       action = EXCLUDED.action,
+                       use_at = EXCLUDED.use_at"#,
+            &player_vec,
+            &action_vec,
+            &id_vec,
+            &use_at_vec
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    sqlx::query!(r#"SELECT update_modified_at ($1)"#, strategy_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await
+}
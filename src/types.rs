@@ -1,12 +1,20 @@
 use crate::protos::stratsync::*;
+use crate::strategy::{StrategyHandle, WriteBehind};
 use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
 use sqlx::{types::Uuid, Pool, Postgres};
-use std::sync::Arc;
-use strum_macros::EnumString;
-use tokio::sync::{mpsc::Sender, Mutex};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+    time::Instant,
+};
+use strum_macros::{Display, EnumString};
+use tokio::sync::{broadcast, mpsc::Sender, OnceCell};
 use tonic::Status;
 
 pub const MAX_COUNTDOWN: i32 = 1800;
+pub const MAX_NOTE_LENGTH: usize = 128;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, sqlx::Type, EnumString)]
 #[sqlx(type_name = "job")]
@@ -36,22 +44,171 @@ pub enum Job {
     VPR,
 }
 
+#[derive(
+    Clone, Copy, Debug, PartialEq, Serialize, Deserialize, sqlx::Type, EnumString, Display,
+)]
+#[sqlx(type_name = "share_scope", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ShareScope {
+    View,
+    Edit,
+    Notes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, sqlx::Type, EnumString, Display)]
+#[sqlx(type_name = "collaborator_role", rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CollaboratorRole {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub token: String,
+    pub address: Option<IpAddr>,
+    pub user_id: Option<Uuid>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PeerContext {
+    pub peer_id: Uuid,
     pub strategy_id: Uuid,
     pub raid_id: Uuid,
+    pub address: Option<IpAddr>,
+    pub user_id: Option<Uuid>,
     pub is_author: bool,
     pub tx: Sender<Result<EventResponse, Status>>,
 }
 
+#[derive(Debug)]
+pub struct HubEvent {
+    pub origin: Option<String>,
+    pub event: event_response::Event,
+}
+
 #[derive(Debug, Clone)]
 pub struct StrategyContext {
     pub raid_id: Uuid,
+    pub revision: i64,
+    pub hub: broadcast::Sender<Arc<HubEvent>>,
     pub peers: Vec<String>,
     pub elevated_peers: Vec<String>,
+    pub note_peers: Vec<String>,
+    pub share_link_peers: HashMap<String, Uuid>,
+    pub share_link_viewers: HashMap<String, Uuid>,
     pub players: Vec<Player>,
     pub damage_options: Vec<DamageOption>,
-    pub entries: Vec<Entry>,
+    pub entries: EntryIndex,
+    pub chat_history: VecDeque<ChatMessage>,
+    pub write_behind: Option<Arc<WriteBehind>>,
+    pub entry_events: PendingEntryEvents,
+    pub idempotency_keys: HashMap<String, VecDeque<IdempotencyRecord>>,
+    pub violations: Vec<Violation>,
+}
+
+// Responses are kept encoded so that any RPC can use it.
+pub type IdempotentResult = Arc<OnceCell<Result<Vec<u8>, Status>>>;
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub method: &'static str,
+    pub request_hash: u64,
+    pub result: IdempotentResult,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PendingEntryEvents {
+    pub origin: Option<String>,
+    pub changes: HashMap<Uuid, Option<EntryState>>,
+    pub deadline: Option<tokio::time::Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntryState {
+    pub player: Uuid,
+    pub action: Uuid,
+    pub use_at: i32,
+}
+
+impl EntryState {
+    pub fn to_entry(&self, id: Uuid) -> Entry {
+        Entry {
+            id: id.to_string(),
+            player: self.player.to_string(),
+            action: self.action.to_string(),
+            use_at: self.use_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EntryIndex {
+    by_id: HashMap<Uuid, EntryState>,
+    by_column: HashMap<(Uuid, Uuid), BTreeSet<(i32, Uuid)>>,
+}
+
+impl EntryIndex {
+    pub fn get(&self, id: &Uuid) -> Option<&EntryState> {
+        self.by_id.get(id)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.by_id.contains_key(id)
+    }
+
+    pub fn insert(&mut self, id: Uuid, entry: EntryState) -> Option<EntryState> {
+        let previous = self.remove(&id);
+
+        self.by_column
+            .entry((entry.player, entry.action))
+            .or_default()
+            .insert((entry.use_at, id));
+        self.by_id.insert(id, entry);
+
+        previous
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<EntryState> {
+        let entry = self.by_id.remove(id)?;
+
+        let key = (entry.player, entry.action);
+        if let Some(column) = self.by_column.get_mut(&key) {
+            column.remove(&(entry.use_at, *id));
+            if column.is_empty() {
+                self.by_column.remove(&key);
+            }
+        }
+
+        Some(entry)
+    }
+
+    pub fn column(&self, player: Uuid, action: Uuid) -> impl Iterator<Item = (Uuid, i32)> + '_ {
+        self.by_column
+            .get(&(player, action))
+            .into_iter()
+            .flatten()
+            .map(|&(use_at, id)| (id, use_at))
+    }
+
+    pub fn ids_of_player(&self, player: Uuid) -> Vec<Uuid> {
+        self.by_column
+            .iter()
+            .filter(|((column_player, _), _)| *column_player == player)
+            .flat_map(|(_, column)| column.iter().map(|&(_, id)| id))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, EntryState)> + '_ {
+        self.by_id.iter().map(|(&id, &entry)| (id, entry))
+    }
+
+    pub fn to_entries(&self) -> Vec<Entry> {
+        self.iter().map(|(id, entry)| entry.to_entry(id)).collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -75,11 +232,41 @@ pub struct ActionInfo {
     pub charges: i32,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ElevationAttempts {
+    pub failures: u32,
+    pub locked_until: Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatRateLimit {
+    pub window_start: Instant,
+    pub count: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct PingState {
+    pub last_sent_at: Option<Instant>,
+    pub pending: Option<PingEvent>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ElevationBans {
+    pub users: Vec<Uuid>,
+    pub addresses: Vec<IpAddr>,
+}
+
 pub struct StratSyncService {
     pub pool: Pool<Postgres>,
+    pub write_behind: bool,
+    pub bind_session_address: bool,
     pub action_cache: Cache<String, Arc<Vec<ActionInfo>>>,
     pub raid_cache: Cache<Uuid, Arc<RaidInfo>>,
-    pub strategy_lock: Cache<Uuid, Arc<Mutex<()>>>,
-    pub strategy_context: Cache<Uuid, Arc<StrategyContext>>,
+    pub strategies: Cache<Uuid, StrategyHandle>,
     pub peer_context: Cache<String, Arc<PeerContext>>,
+    pub strategy_elevation_attempts: Cache<Uuid, ElevationAttempts>,
+    pub address_elevation_attempts: Cache<IpAddr, ElevationAttempts>,
+    pub elevation_bans: Cache<Uuid, ElevationBans>,
+    pub chat_rate_limits: Cache<String, ChatRateLimit>,
+    pub ping_states: Cache<String, Arc<std::sync::Mutex<PingState>>>,
 }
@@ -1,39 +1,371 @@
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, jwk::JwkSet, Algorithm, DecodingKey, EncodingKey, Header,
+    Validation,
+};
+use moka::sync::Cache;
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 use std::{
-    env,
-    sync::{Arc, OnceLock},
+    collections::HashMap,
+    env, fs,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::{error::TrySendError, Sender, WeakSender},
 };
-use tokio::task::JoinSet;
-use tonic::{metadata::MetadataMap, Status};
+use tonic::{metadata::MetadataMap, Code, Request, Response, Status};
 
 use crate::{
-    protos::stratsync::{event_response, EventResponse},
+    protos::stratsync::{
+        event_response, DamageOption, EventResponse, KickedEvent, Note, PermissionChangedEvent,
+        Player,
+    },
+    strategy,
     types::*,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
+    pub exp: usize,
+    pub sub: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShareClaims {
     pub aud: String,
     pub exp: usize,
     pub iat: usize,
-    pub iss: String,
-    pub sub: String,
+    pub jti: String,
+    pub strategy: String,
+    pub scope: ShareScope,
 }
 
+const SHARE_AUDIENCE: &str = "share";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+const MAX_IDEMPOTENCY_KEYS_PER_SESSION: usize = 64;
+const FORWARD_RETRY_DELAY: Duration = Duration::from_millis(20);
+
 pub fn parse_string_to_uuid(id: &str, message: impl Into<String>) -> Result<Uuid, Status> {
     Uuid::parse_str(id).map_err(|_| Status::invalid_argument(message))
 }
 
-static DECODING_KEY: OnceLock<DecodingKey> = OnceLock::new();
+fn col_sweeping(use_ats: impl IntoIterator<Item = i32>, cooldown: i32) -> Vec<(i32, i32)> {
+    let mut col_sweeping: Vec<(i32, i32)> = Vec::new();
+    for use_at in use_ats {
+        col_sweeping.push((use_at, 1));
+        col_sweeping.push((use_at + cooldown, -1));
+    }
+    col_sweeping.sort();
 
-pub fn parse_authorization_header(metadata: &MetadataMap) -> Result<Option<Uuid>, Status> {
-    let decoding_key = DECODING_KEY.get_or_init(|| {
-        let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set in the environment");
-        DecodingKey::from_secret(jwt_secret.as_bytes())
-    });
+    col_sweeping
+}
+
+pub fn max_simultaneous_uses(use_ats: impl IntoIterator<Item = i32>, cooldown: i32) -> i32 {
+    let col_sweeping = col_sweeping(use_ats, cooldown);
+
+    let mut max_simultaneous_uses = 0;
+    let mut current_uses = 0;
+    for (_, delta) in col_sweeping {
+        current_uses += delta;
+        max_simultaneous_uses = max_simultaneous_uses.max(current_uses);
+    }
+
+    max_simultaneous_uses
+}
+
+// Inclusive ranges within [start, end] where one more use fits.
+pub fn available_use_ats(
+    use_ats: impl IntoIterator<Item = i32>,
+    cooldown: i32,
+    charges: i32,
+    start: i32,
+    end: i32,
+) -> Vec<(i32, i32)> {
+    if charges <= 0 || start > end {
+        return vec![];
+    }
+
+    let col_sweeping = col_sweeping(use_ats, cooldown);
+
+    // Half-open ranges during which every charge is on cooldown
+    let mut exhausted: Vec<(i32, i32)> = Vec::new();
+    let mut exhausted_since = None;
+    let mut current_uses = 0;
+    for (i, &(time, delta)) in col_sweeping.iter().enumerate() {
+        current_uses += delta;
+        if current_uses > charges {
+            // Already over the limit, so any additional use is rejected
+            return vec![];
+        }
+        if col_sweeping
+            .get(i + 1)
+            .is_some_and(|&(next, _)| next == time)
+        {
+            continue;
+        }
+
+        match exhausted_since {
+            None if current_uses >= charges => exhausted_since = Some(time),
+            Some(since) if current_uses < charges => {
+                exhausted.push((since, time));
+                exhausted_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    // A use at `x` is on cooldown during `[x, x + cooldown)`, so it overlaps an
+    // exhausted range `[a, b)` exactly when `a - cooldown < x < b`.
+    let mut available = Vec::new();
+    let mut next = start;
+    for (since, until) in exhausted {
+        let blocked_from = since - cooldown + 1;
+        if blocked_from > next {
+            available.push((next, (blocked_from - 1).min(end)));
+        }
+
+        next = next.max(until);
+        if next > end {
+            return available;
+        }
+    }
+    available.push((next, end));
+
+    available
+}
+
+pub fn validate_note(note: &Note, raid: &RaidInfo) -> Result<(), Status> {
+    if note.block < 1 || note.block > raid.headcount + 1 {
+        return Err(Status::invalid_argument("Block is out of range"));
+    }
+
+    if note.offset < 0f32 || note.offset > 1f32 {
+        return Err(Status::invalid_argument("Offset is out of range"));
+    }
+
+    if note.at < -MAX_COUNTDOWN || note.at > raid.duration {
+        return Err(Status::invalid_argument("At is out of range"));
+    }
+
+    if note.content.len() > MAX_NOTE_LENGTH {
+        return Err(Status::invalid_argument("Note text is too long"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_damage_option(
+    damage_option: &DamageOption,
+    raid: &RaidInfo,
+    players: &[Player],
+) -> Result<(), Status> {
+    let damage = Uuid::parse_str(&damage_option.damage)
+        .ok()
+        .and_then(|damage_id| raid.damages.iter().find(|damage| damage.id == damage_id))
+        .ok_or_else(|| {
+            Status::failed_precondition("Damage not found or not belongs to the specified raid")
+        })?;
+
+    if let Some(s) = damage_option.num_shared {
+        if s > damage.max_shared {
+            return Err(Status::failed_precondition(
+                "num_shared is greater than max_shared",
+            ));
+        }
+    }
+
+    if let Some(s) = &damage_option.primary_target {
+        if !Uuid::parse_str(s)
+            .is_ok_and(|s| players.iter().any(|player| player.id == s.to_string()))
+        {
+            return Err(Status::failed_precondition("Primary target not found"));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn cache_actions(
+    action_cache: &Cache<String, Arc<Vec<ActionInfo>>>,
+    actions: impl IntoIterator<Item = (String, ActionInfo)>,
+) {
+    let mut actions_by_job: HashMap<String, Vec<ActionInfo>> = HashMap::new();
+    for (job, action) in actions {
+        actions_by_job.entry(job).or_default().push(action);
+    }
 
+    for (job, _) in action_cache.iter() {
+        if !actions_by_job.contains_key(job.as_str()) {
+            action_cache.invalidate(job.as_str());
+        }
+    }
+
+    for (job, actions) in actions_by_job {
+        action_cache.insert(job, Arc::new(actions));
+    }
+}
+
+const JWKS_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+struct JwtConfig {
+    secret_key: Option<DecodingKey>,
+    jwks_path: Option<PathBuf>,
+    audience: Vec<String>,
+    issuer: Option<String>,
+}
+
+#[derive(Default)]
+struct Jwks {
+    keys: HashMap<String, DecodingKey>,
+    modified_at: Option<SystemTime>,
+    checked_at: Option<Instant>,
+}
+
+static JWT_CONFIG: OnceLock<JwtConfig> = OnceLock::new();
+static JWKS: OnceLock<RwLock<Jwks>> = OnceLock::new();
+
+fn jwt_config() -> &'static JwtConfig {
+    JWT_CONFIG.get_or_init(|| {
+        let secret_key = env::var("JWT_SECRET")
+            .ok()
+            .map(|jwt_secret| DecodingKey::from_secret(jwt_secret.as_bytes()));
+        let jwks_path = env::var("JWT_JWKS_PATH").ok().map(PathBuf::from);
+
+        if secret_key.is_none() && jwks_path.is_none() {
+            panic!("JWT_SECRET or JWT_JWKS_PATH must be set in the environment");
+        }
+
+        let audience = env::var("JWT_AUDIENCE")
+            .unwrap_or_else(|_| "authenticated".to_string())
+            .split(',')
+            .map(|audience| audience.trim().to_string())
+            .filter(|audience| !audience.is_empty())
+            .collect();
+        let issuer = env::var("JWT_ISSUER").ok();
+
+        JwtConfig {
+            secret_key,
+            jwks_path,
+            audience,
+            issuer,
+        }
+    })
+}
+
+fn load_jwks(path: &Path) -> Result<(HashMap<String, DecodingKey>, SystemTime), String> {
+    let modified_at = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| err.to_string())?;
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let jwk_set: JwkSet = serde_json::from_str(&contents).map_err(|err| err.to_string())?;
+
+    let keys = jwk_set
+        .keys
+        .iter()
+        .filter_map(|jwk| {
+            let kid = jwk.common.key_id.clone()?;
+            match DecodingKey::from_jwk(jwk) {
+                Ok(key) => Some((kid, key)),
+                Err(err) => {
+                    tracing::warn!(kid, error = %err, "Skipping unsupported JWK");
+                    None
+                }
+            }
+        })
+        .collect();
+
+    Ok((keys, modified_at))
+}
+
+fn is_jwks_fresh(jwks: &Jwks) -> bool {
+    jwks.checked_at
+        .is_some_and(|checked_at| checked_at.elapsed() < JWKS_RELOAD_INTERVAL)
+}
+
+async fn find_jwk(path: &Path, kid: &str) -> Option<DecodingKey> {
+    let jwks = JWKS.get_or_init(|| RwLock::new(Jwks::default()));
+
+    {
+        let jwks = jwks.read().unwrap();
+        if is_jwks_fresh(&jwks) {
+            return jwks.keys.get(kid).cloned();
+        }
+    }
+
+    // Unknown key ids are only looked up again on the next reload, and other
+    // requests keep using the current keys while this one reloads.
+    let known_modified_at = {
+        let mut jwks = jwks.write().unwrap();
+        if is_jwks_fresh(&jwks) {
+            return jwks.keys.get(kid).cloned();
+        }
+
+        jwks.checked_at = Some(Instant::now());
+        jwks.modified_at
+    };
+
+    let path = path.to_owned();
+    let loaded = tokio::task::spawn_blocking(move || {
+        let modified_at = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        if modified_at.is_some() && modified_at == known_modified_at {
+            return None;
+        }
+
+        Some(load_jwks(&path))
+    })
+    .await
+    .unwrap();
+
+    let mut jwks = jwks.write().unwrap();
+    match loaded {
+        Some(Ok((keys, modified_at))) => {
+            jwks.keys = keys;
+            jwks.modified_at = Some(modified_at);
+        }
+        Some(Err(err)) => tracing::error!(error = %err, "Failed to load JWKS"),
+        None => {}
+    }
+
+    jwks.keys.get(kid).cloned()
+}
+
+async fn decoding_key_for(header: &Header) -> Result<DecodingKey, Status> {
+    let config = jwt_config();
+
+    match header.alg {
+        Algorithm::HS256 => config
+            .secret_key
+            .clone()
+            .ok_or_else(|| Status::unauthenticated("Unsupported token algorithm")),
+        Algorithm::RS256 | Algorithm::ES256 => {
+            let jwks_path = config
+                .jwks_path
+                .as_deref()
+                .ok_or_else(|| Status::unauthenticated("Unsupported token algorithm"))?;
+            let kid = header
+                .kid
+                .as_deref()
+                .ok_or_else(|| Status::unauthenticated("Token has no key id"))?;
+
+            find_jwk(jwks_path, kid)
+                .await
+                .ok_or_else(|| Status::unauthenticated("Unknown signing key"))
+        }
+        _ => Err(Status::unauthenticated("Unsupported token algorithm")),
+    }
+}
+
+pub async fn parse_authorization_header(metadata: &MetadataMap) -> Result<Option<Uuid>, Status> {
     let authorization = match metadata.get("authorization") {
         Some(value) => value,
         None => return Ok(None),
@@ -53,10 +385,18 @@ pub fn parse_authorization_header(metadata: &MetadataMap) -> Result<Option<Uuid>
         ));
     }
 
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.set_audience(&["authenticated"]);
+    let header =
+        decode_header(token).map_err(|_| Status::unauthenticated("Failed to decode token"))?;
+    let decoding_key = decoding_key_for(&header).await?;
 
-    let claims = decode::<Claims>(token, decoding_key, &validation)
+    let config = jwt_config();
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&config.audience);
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+
+    let claims = decode::<Claims>(token, &decoding_key, &validation)
         .map_err(|err| match err.kind() {
             jsonwebtoken::errors::ErrorKind::InvalidToken => {
                 Status::unauthenticated("Invalid token")
@@ -73,36 +413,239 @@ pub fn parse_authorization_header(metadata: &MetadataMap) -> Result<Option<Uuid>
     Ok(Some(user_id))
 }
 
+pub async fn parse_session<T>(request: &Request<T>) -> Result<Session, Status> {
+    let token = request
+        .metadata()
+        .get("x-session-token")
+        .ok_or_else(|| Status::unauthenticated("Session token is missing"))?
+        .to_str()
+        .map_err(|_| Status::invalid_argument("Invalid session token header"))?
+        .to_owned();
+
+    Ok(Session {
+        token,
+        address: request.remote_addr().map(|addr| addr.ip()),
+        user_id: parse_authorization_header(request.metadata()).await?,
+    })
+}
+
+impl StratSyncService {
+    // A retry with a recent key gets the original result, or runs the RPC again
+    // if it failed transiently.
+    pub async fn idempotent<Req, Res, F, Fut>(
+        &self,
+        request: Request<Req>,
+        rpc: F,
+    ) -> Result<Response<Res>, Status>
+    where
+        F: FnOnce(Request<Req>) -> Fut,
+        Fut: Future<Output = Result<Response<Res>, Status>>,
+        Req: Message,
+        Res: Message + Default,
+    {
+        let key = match request.metadata().get("x-idempotency-key") {
+            Some(key) => key
+                .to_str()
+                .map_err(|_| Status::invalid_argument("Invalid idempotency key header"))?
+                .to_owned(),
+            None => return rpc(request).await,
+        };
+
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+            return Err(Status::invalid_argument("Invalid idempotency key header"));
+        }
+
+        let method = std::any::type_name::<Req>();
+        let request_hash = {
+            let mut hasher = DefaultHasher::new();
+            request.get_ref().encode_to_vec().hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let session = parse_session(&request).await?;
+        let peer_context = self.session_peer_context(&session)?;
+
+        let result = {
+            let mut strategy_context = self
+                .strategies
+                .get(&peer_context.strategy_id)
+                .ok_or_else(|| Status::unauthenticated("Strategy context not opened"))?
+                .acquire()
+                .await
+                .ok_or_else(|| Status::unauthenticated("Strategy context not opened"))?;
+
+            let keys = strategy_context
+                .idempotency_keys
+                .entry(session.token)
+                .or_default();
+
+            match keys.iter().find(|record| record.key == key) {
+                Some(record) if record.method != method || record.request_hash != request_hash => {
+                    return Err(Status::invalid_argument(
+                        "Idempotency key was used for a different request",
+                    ));
+                }
+                Some(record) => record.result.clone(),
+                None => {
+                    let result = IdempotentResult::default();
+                    keys.push_back(IdempotencyRecord {
+                        key,
+                        method,
+                        request_hash,
+                        result: result.clone(),
+                    });
+                    if keys.len() > MAX_IDEMPOTENCY_KEYS_PER_SESSION {
+                        keys.pop_front();
+                    }
+                    result
+                }
+            }
+        };
+
+        // If the request that started the call was cancelled, the retry runs it.
+        result
+            .get_or_try_init(|| async {
+                match rpc(request).await {
+                    Ok(response) => Ok(Ok(response.into_inner().encode_to_vec())),
+                    Err(status) if is_final_rejection(&status) => Ok(Err(status)),
+                    Err(status) => Err(status),
+                }
+            })
+            .await?
+            .clone()
+            .and_then(|bytes| {
+                Res::decode(bytes.as_slice())
+                    .map(Response::new)
+                    .map_err(|_| Status::internal("Failed to decode the original response"))
+            })
+    }
+}
+
+// Rejections that a retry of the same request would get again.
+fn is_final_rejection(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::InvalidArgument
+            | Code::NotFound
+            | Code::AlreadyExists
+            | Code::PermissionDenied
+            | Code::FailedPrecondition
+            | Code::OutOfRange
+    )
+}
+
+static SHARE_KEYS: OnceLock<(EncodingKey, DecodingKey)> = OnceLock::new();
+
+// Reads the signing keys up front, so that a missing variable stops the server
+// at startup instead of panicking the first request that needs it.
+pub fn load_keys() {
+    jwt_config();
+    share_keys();
+}
+
+fn share_keys() -> &'static (EncodingKey, DecodingKey) {
+    SHARE_KEYS.get_or_init(|| {
+        let share_secret = env::var("SHARE_TOKEN_SECRET")
+            .expect("SHARE_TOKEN_SECRET must be set in the environment");
+        (
+            EncodingKey::from_secret(share_secret.as_bytes()),
+            DecodingKey::from_secret(share_secret.as_bytes()),
+        )
+    })
+}
+
+pub fn encode_share_token(
+    link_id: Uuid,
+    strategy_id: Uuid,
+    scope: ShareScope,
+    expires_in: Duration,
+) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    let claims = ShareClaims {
+        aud: SHARE_AUDIENCE.to_string(),
+        exp: (now + expires_in).as_secs() as usize,
+        iat: now.as_secs() as usize,
+        jti: link_id.to_string(),
+        strategy: strategy_id.to_string(),
+        scope,
+    };
+
+    encode(&Header::new(Algorithm::HS256), &claims, &share_keys().0).unwrap()
+}
+
+pub fn parse_share_token(token: &str) -> Result<ShareClaims, Status> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[SHARE_AUDIENCE]);
+
+    decode::<ShareClaims>(token, &share_keys().1, &validation)
+        .map(|data| data.claims)
+        .map_err(|err| match err.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
+                Status::permission_denied("Share link has expired")
+            }
+            _ => Status::permission_denied("Invalid share link"),
+        })
+}
+
 macro_rules! open_strategy {
-    ($self: ident, $token: expr, $peer_context:ident, $lock:ident, $guard:ident, $strategy_context:ident) => {
-        let $peer_context = $self
-            .peer_context
-            .get($token)
-            .ok_or_else(|| Status::unauthenticated("Invalid token or peer context not found"))?;
+    ($self: ident, $session: expr, $peer_context:ident, $lock:ident, $guard:ident, $strategy_context:ident) => {
+        let $peer_context = $self.session_peer_context($session)?;
         let $lock = $self
-            .strategy_lock
-            .get(&$peer_context.strategy_id)
-            .ok_or_else(|| Status::internal("Strategy lock not found"))?;
-        let $guard = $lock.lock().await;
-        let $strategy_context = $self
-            .strategy_context
+            .strategies
             .get(&$peer_context.strategy_id)
             .ok_or_else(|| Status::unauthenticated("Strategy context not opened"))?;
+        let mut $guard = $lock
+            .acquire()
+            .await
+            .ok_or_else(|| Status::unauthenticated("Strategy context not opened"))?;
+        let $strategy_context = &mut *$guard;
     };
 }
 
 macro_rules! open_strategy_elevated {
-    ($self: ident, $token: expr, $peer_context:ident, $lock:ident, $guard:ident, $strategy_context:ident) => {
+    ($self: ident, $session: expr, $peer_context:ident, $lock:ident, $guard:ident, $strategy_context:ident) => {
         utils::open_strategy!(
             $self,
-            $token,
+            $session,
             $peer_context,
             $lock,
             $guard,
             $strategy_context
         );
 
-        if !$strategy_context.elevated_peers.iter().any(|s| s == $token) {
+        if !$strategy_context
+            .elevated_peers
+            .iter()
+            .any(|s| *s == $session.token)
+        {
+            return Err(Status::permission_denied(
+                "Insufficient permissions: peer is not elevated",
+            ));
+        }
+    };
+}
+
+macro_rules! open_strategy_notes_elevated {
+    ($self: ident, $session: expr, $peer_context:ident, $lock:ident, $guard:ident, $strategy_context:ident) => {
+        utils::open_strategy!(
+            $self,
+            $session,
+            $peer_context,
+            $lock,
+            $guard,
+            $strategy_context
+        );
+
+        if !$strategy_context
+            .elevated_peers
+            .iter()
+            .any(|s| *s == $session.token)
+            && !$strategy_context
+                .note_peers
+                .iter()
+                .any(|s| *s == $session.token)
+        {
             return Err(Status::permission_denied(
                 "Insufficient permissions: peer is not elevated",
             ));
@@ -112,39 +655,534 @@ macro_rules! open_strategy_elevated {
 
 pub(crate) use open_strategy;
 pub(crate) use open_strategy_elevated;
+pub(crate) use open_strategy_notes_elevated;
+
+pub fn permission_of(peer: &String, strategy_context: &StrategyContext) -> ShareScope {
+    if strategy_context.elevated_peers.contains(peer) {
+        ShareScope::Edit
+    } else if strategy_context.note_peers.contains(peer) {
+        ShareScope::Notes
+    } else {
+        ShareScope::View
+    }
+}
 
 impl StratSyncService {
-    pub async fn broadcast(
+    pub fn session_peer_context(&self, session: &Session) -> Result<Arc<PeerContext>, Status> {
+        let peer_context = self
+            .peer_context
+            .get(&session.token)
+            .ok_or_else(|| Status::unauthenticated("Invalid token or peer context not found"))?;
+
+        // Anonymous sessions have nothing else to bind to. Signed-in users may
+        // change networks, so binding their address as well is opt-in.
+        let is_address_bound = session.user_id.is_none() || self.bind_session_address;
+
+        if peer_context.user_id != session.user_id
+            || (is_address_bound && peer_context.address != session.address)
+        {
+            return Err(Status::unauthenticated(
+                "Session token was issued to another client",
+            ));
+        }
+
+        Ok(peer_context)
+    }
+
+    pub fn is_elevation_banned(
+        &self,
+        strategy_id: Uuid,
+        user_id: Option<Uuid>,
+        address: Option<IpAddr>,
+    ) -> bool {
+        match self.elevation_bans.get(&strategy_id) {
+            Some(bans) => {
+                user_id.is_some_and(|user_id| bans.users.contains(&user_id))
+                    || address.is_some_and(|address| bans.addresses.contains(&address))
+            }
+            None => false,
+        }
+    }
+
+    pub fn kick(&self, peer: &String, reason: Option<String>) {
+        // A full buffer must not hold up the strategy, and the peer is evicted
+        // regardless.
+        if let Some(peer_context) = self.peer_context.get(peer) {
+            peer_context
+                .tx
+                .try_send(Ok(EventResponse {
+                    event: Some(event_response::Event::KickedEvent(KickedEvent { reason })),
+                }))
+                .ok();
+        }
+
+        self.peer_context.invalidate(peer);
+    }
+
+    // For events that correct a peer's state. If one can't be queued, the peer
+    // is dropped and resynchronizes when it subscribes again.
+    pub fn send_or_kick(&self, peer: &String, event: event_response::Event) {
+        let is_sent = self.peer_context.get(peer).is_some_and(|peer_context| {
+            peer_context
+                .tx
+                .try_send(Ok(EventResponse { event: Some(event) }))
+                .is_ok()
+        });
+
+        if !is_sent {
+            self.kick(peer, None);
+        }
+    }
+
+    pub async fn verify_share_token(
+        &self,
+        share_token: &str,
+        strategy_id: Uuid,
+    ) -> Result<(Uuid, ShareScope), Status> {
+        let claims = parse_share_token(share_token)?;
+        let link_id = Uuid::parse_str(&claims.jti)
+            .map_err(|_| Status::permission_denied("Invalid share link"))?;
+
+        if claims.strategy != strategy_id.to_string() {
+            return Err(Status::permission_denied(
+                "Share link does not belong to this strategy",
+            ));
+        }
+
+        let is_active = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1
+                                FROM public.strategy_share_links
+                               WHERE id = $1
+                                 AND strategy = $2
+                                 AND NOT revoked
+                                 AND expires_at > now()) AS "is_active!""#,
+            link_id,
+            strategy_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap()
+        .is_active;
+
+        if !is_active {
+            return Err(Status::permission_denied("Share link has been revoked"));
+        }
+
+        Ok((link_id, claims.scope))
+    }
+
+    pub async fn fetch_collaborator_role(
+        &self,
+        strategy_id: Uuid,
+        user_id: Uuid,
+    ) -> Option<CollaboratorRole> {
+        sqlx::query!(
+            r#"SELECT role AS "role: CollaboratorRole"
+                 FROM public.strategy_collaborators
+                WHERE strategy = $1 AND "user" = $2"#,
+            strategy_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .unwrap()
+        .map(|row| row.role)
+    }
+
+    pub fn apply_collaborator_role(
+        &self,
+        strategy_context: &mut StrategyContext,
+        user_id: Uuid,
+        role: Option<CollaboratorRole>,
+        author: Option<Uuid>,
+        is_public: bool,
+        is_editable: bool,
+    ) {
+        let affected_peers: Vec<(String, Arc<PeerContext>)> = strategy_context
+            .peers
+            .iter()
+            .filter_map(|peer| {
+                self.peer_context
+                    .get(peer)
+                    .filter(|peer_context| peer_context.user_id == Some(user_id))
+                    .map(|peer_context| (peer.to_owned(), peer_context))
+            })
+            .collect();
+
+        if affected_peers.is_empty() {
+            return;
+        }
+
+        let is_author = Some(user_id) == author || role == Some(CollaboratorRole::Owner);
+        let is_elevated = is_author || (role == Some(CollaboratorRole::Editor) && is_editable);
+        let has_access = is_public || is_author || role.is_some();
+
+        for (peer, peer_context) in &affected_peers {
+            strategy_context
+                .elevated_peers
+                .retain(|elevated_peer| elevated_peer != peer);
+            if is_elevated {
+                strategy_context.elevated_peers.push(peer.to_owned());
+            }
+
+            if peer_context.is_author != is_author {
+                let mut peer_context_after = (**peer_context).to_owned();
+                peer_context_after.is_author = is_author;
+                self.peer_context
+                    .insert(peer.to_owned(), Arc::new(peer_context_after));
+            }
+        }
+
+        for (peer, _) in &affected_peers {
+            if has_access {
+                self.notify_permission_changed(peer, strategy_context);
+            } else {
+                self.kick(peer, Some("Access to the strategy was revoked".to_string()));
+            }
+        }
+    }
+
+    pub fn demote_peers(&self, strategy_context: &mut StrategyContext) {
+        let demoted_peers: Vec<String> = strategy_context
+            .peers
+            .iter()
+            .filter(|&peer| {
+                strategy_context.elevated_peers.contains(peer)
+                    || strategy_context.note_peers.contains(peer)
+            })
+            .filter(|&peer| {
+                !self
+                    .peer_context
+                    .get(peer)
+                    .is_some_and(|peer_context| peer_context.is_author)
+            })
+            .map(|peer| peer.to_owned())
+            .collect();
+
+        if demoted_peers.is_empty() {
+            return;
+        }
+
+        strategy_context
+            .elevated_peers
+            .retain(|peer| !demoted_peers.contains(peer));
+        strategy_context
+            .note_peers
+            .retain(|peer| !demoted_peers.contains(peer));
+        for peer in &demoted_peers {
+            strategy_context.share_link_peers.remove(peer);
+        }
+
+        for peer in &demoted_peers {
+            self.notify_permission_changed(peer, strategy_context);
+        }
+    }
+
+    pub fn notify_permission_changed(&self, peer: &String, strategy_context: &StrategyContext) {
+        let peer_context = match self.peer_context.get(peer) {
+            Some(peer_context) => peer_context,
+            None => return,
+        };
+
+        let permission = permission_of(peer, strategy_context);
+
+        self.send_or_kick(
+            peer,
+            event_response::Event::PermissionChangedEvent(PermissionChangedEvent {
+                permission: permission.to_string(),
+                is_author: peer_context.is_author,
+            }),
+        );
+    }
+
+    pub async fn persist_entries(
+        &self,
+        strategy_id: Uuid,
+        strategy_context: &StrategyContext,
+        changes: Vec<(Uuid, Option<EntryState>)>,
+    ) {
+        self.persist(
+            strategy_id,
+            strategy_context,
+            strategy::PendingWrites {
+                entries: changes.into_iter().collect(),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    pub async fn persist(
+        &self,
+        strategy_id: Uuid,
+        strategy_context: &StrategyContext,
+        writes: strategy::PendingWrites,
+    ) {
+        if writes.is_empty() {
+            return;
+        }
+
+        match &strategy_context.write_behind {
+            Some(write_behind) => write_behind.push(writes),
+            None => strategy::write_changes(&self.pool, strategy_id, &writes)
+                .await
+                .unwrap(),
+        }
+    }
+
+    // Jobs without any action in the catalog have no cache entry.
+    pub fn job_actions(&self, job: &str) -> Arc<Vec<ActionInfo>> {
+        self.action_cache.get(job).unwrap_or_default()
+    }
+
+    // The catalog can change while the server is running, so it is read again
+    // whenever a strategy is loaded or validated.
+    pub async fn reload_catalog(&self, raid_id: Uuid) -> Result<(), Status> {
+        let (actions, damages, row) = tokio::try_join!(
+            sqlx::query!(
+                r#"SELECT id, job AS "job: String", cooldown, charges
+                     FROM public.actions"#
+            )
+            .fetch_all(&self.pool),
+            sqlx::query_as!(
+                Damage,
+                r#"SELECT d.id, max_shared, num_targets
+                     FROM public.damages AS d
+                          JOIN public.gimmicks AS g
+                          ON d.gimmick = g.id
+                    WHERE g.raid = $1"#,
+                raid_id
+            )
+            .fetch_all(&self.pool),
+            sqlx::query!(
+                r#"SELECT duration, headcount
+                     FROM public.raids
+                    WHERE id = $1"#,
+                raid_id
+            )
+            .fetch_one(&self.pool),
+        )
+        .map_err(|_| Status::internal("Failed to load the catalog"))?;
+
+        cache_actions(
+            &self.action_cache,
+            actions.into_iter().map(|row| {
+                (
+                    row.job,
+                    ActionInfo {
+                        id: row.id,
+                        cooldown: row.cooldown,
+                        charges: row.charges,
+                    },
+                )
+            }),
+        );
+
+        self.raid_cache.insert(
+            raid_id,
+            Arc::new(RaidInfo {
+                duration: row.duration,
+                headcount: row.headcount,
+                damages,
+            }),
+        );
+
+        Ok(())
+    }
+
+    pub fn broadcast(
         &self,
         token: &String,
-        strategy_context: &Arc<StrategyContext>,
+        strategy_context: &mut StrategyContext,
         event: event_response::Event,
     ) {
-        let mut tasks = JoinSet::new();
+        // Coalesced entry changes were made before this event and must reach
+        // peers first.
+        strategy_context.flush_entry_events();
+        publish(&strategy_context.hub, Some(token), event);
+    }
+}
 
-        for peer in &strategy_context.peers {
-            if token == peer {
-                continue;
-            }
+pub fn publish(
+    hub: &broadcast::Sender<Arc<HubEvent>>,
+    origin: Option<&String>,
+    event: event_response::Event,
+) {
+    // Sending only fails when no peer is subscribed, which is fine to ignore.
+    hub.send(Arc::new(HubEvent {
+        origin: origin.cloned(),
+        event,
+    }))
+    .ok();
+}
+
+// Only a weak sender is held so that invalidating the peer context still
+// closes its stream.
+pub fn spawn_hub_forwarder(
+    token: String,
+    tx: &Sender<Result<EventResponse, Status>>,
+    mut hub: broadcast::Receiver<Arc<HubEvent>>,
+) {
+    let tx = tx.downgrade();
 
-            let tx = match self.peer_context.get(peer) {
-                Some(peer_context) => peer_context.tx.clone(),
-                None => continue,
+    tokio::spawn(async move {
+        loop {
+            let response = match hub.recv().await {
+                Ok(hub_event) => {
+                    if hub_event.origin.as_ref() == Some(&token) {
+                        continue;
+                    }
+
+                    Ok(EventResponse {
+                        event: Some(hub_event.event.clone()),
+                    })
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    forward(
+                        &tx,
+                        Err(Status::data_loss(format!(
+                            "Missed {} events, please resubscribe",
+                            skipped
+                        ))),
+                    )
+                    .await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
             };
-            let event = event.clone();
 
-            if tx.is_closed() {
-                self.peer_context.invalidate(peer);
-                continue;
+            if !forward(&tx, response).await {
+                break;
             }
+        }
+    });
+}
+
+// Waits for room in the stream without holding a sender across the wait, so
+// evicting the peer closes its stream even if it stopped reading.
+async fn forward(
+    tx: &WeakSender<Result<EventResponse, Status>>,
+    mut response: Result<EventResponse, Status>,
+) -> bool {
+    loop {
+        let sender = match tx.upgrade() {
+            Some(sender) => sender,
+            None => return false,
+        };
 
-            tasks.spawn(async move {
-                tx.send(Ok(EventResponse { event: Some(event) }))
-                    .await
-                    .unwrap()
-            });
+        match sender.try_send(response) {
+            Ok(()) => return true,
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(returned)) => response = returned,
         }
 
-        while (tasks.join_next().await).is_some() {}
+        drop(sender);
+        tokio::time::sleep(FORWARD_RETRY_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_available(ranges: &[(i32, i32)], use_at: i32) -> bool {
+        ranges
+            .iter()
+            .any(|&(from, to)| from <= use_at && use_at <= to)
+    }
+
+    #[test]
+    fn col_sweeping_ends_uses_before_starting_new_ones() {
+        assert_eq!(
+            col_sweeping([10, 0], 10),
+            vec![(0, 1), (10, -1), (10, 1), (20, -1)]
+        );
+    }
+
+    #[test]
+    fn max_simultaneous_uses_counts_overlapping_cooldowns() {
+        assert_eq!(max_simultaneous_uses([], 10), 0);
+        assert_eq!(max_simultaneous_uses([0, 10, 20], 10), 1);
+        assert_eq!(max_simultaneous_uses([0, 9], 10), 2);
+        assert_eq!(max_simultaneous_uses([0, 5, 9, 15], 10), 3);
+    }
+
+    #[test]
+    fn available_use_ats_of_empty_column() {
+        assert_eq!(
+            available_use_ats([], 60, 1, -MAX_COUNTDOWN, 600),
+            vec![(-MAX_COUNTDOWN, 600)]
+        );
+        assert_eq!(available_use_ats([], 60, 0, -MAX_COUNTDOWN, 600), vec![]);
+        assert_eq!(available_use_ats([], 60, 1, 600, -MAX_COUNTDOWN), vec![]);
+    }
+
+    #[test]
+    fn available_use_ats_allows_back_to_back_uses() {
+        let available = available_use_ats([100], 60, 1, -MAX_COUNTDOWN, 600);
+
+        assert_eq!(available, vec![(-MAX_COUNTDOWN, 40), (160, 600)]);
+        assert!(is_available(&available, 40));
+        assert!(is_available(&available, 160));
+        assert!(!is_available(&available, 41));
+        assert!(!is_available(&available, 159));
+    }
+
+    #[test]
+    fn available_use_ats_with_multiple_charges() {
+        assert_eq!(
+            available_use_ats([0, 10], 60, 2, -MAX_COUNTDOWN, 600),
+            vec![(-MAX_COUNTDOWN, -50), (60, 600)]
+        );
+        assert_eq!(
+            available_use_ats([0], 60, 2, -MAX_COUNTDOWN, 600),
+            vec![(-MAX_COUNTDOWN, 600)]
+        );
+    }
+
+    #[test]
+    fn available_use_ats_of_column_over_the_limit() {
+        assert_eq!(
+            available_use_ats([0, 5], 60, 1, -MAX_COUNTDOWN, 600),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn available_use_ats_is_clipped_to_bounds() {
+        assert_eq!(
+            available_use_ats([-MAX_COUNTDOWN + 10], 60, 1, -MAX_COUNTDOWN, 600),
+            vec![(-MAX_COUNTDOWN + 70, 600)]
+        );
+        assert_eq!(
+            available_use_ats([590], 60, 1, -MAX_COUNTDOWN, 600),
+            vec![(-MAX_COUNTDOWN, 530)]
+        );
+        assert_eq!(available_use_ats([0], 1000, 1, -100, 100), vec![]);
+    }
+
+    #[test]
+    fn available_use_ats_matches_max_simultaneous_uses() {
+        let columns: [&[i32]; 5] = [&[], &[0], &[-20, 0], &[0, 10, 30], &[-30, -25, 5, 12, 40]];
+
+        for use_ats in columns {
+            for charges in 1..=3 {
+                if max_simultaneous_uses(use_ats.iter().copied(), 10) > charges {
+                    continue;
+                }
+
+                let available = available_use_ats(use_ats.iter().copied(), 10, charges, -30, 50);
+                for use_at in -30..=50 {
+                    let fits = max_simultaneous_uses(use_ats.iter().copied().chain([use_at]), 10)
+                        <= charges;
+
+                    assert_eq!(
+                        is_available(&available, use_at),
+                        fits,
+                        "use_ats: {use_ats:?}, charges: {charges}, use_at: {use_at}"
+                    );
+                }
+            }
+        }
     }
 }